@@ -1,18 +1,28 @@
 use std::{
+    cell::{Cell, RefCell},
     io::{self, Write},
     path::Path,
+    rc::Rc,
 };
 
-use git2::{BranchType, Error, PushOptions, Repository};
+use git2::{BranchType, Error, ErrorClass, ErrorCode, Oid, PushOptions, Repository};
 use owo_colors::OwoColorize;
+use serde::Serialize;
 
-use crate::helpers::{create_callbacks, has_remote, show_progress};
+use crate::helpers::{
+    commit_signature, committer_signature, create_callbacks, gg_config_str, has_remote,
+    main_branch_name, show_progress,
+};
+use crate::ssh::open_remote;
+use crate::stack::set_branch_parent;
+use crate::sync::{clear_queued_push, queue_push};
 
 pub fn commit_all(repo: &Repository, message: &str, amend: bool) -> Result<(), git2::Error> {
     let mut index = repo.index()?;
     let oid = index.write_tree()?;
     let tree = repo.find_tree(oid)?;
-    let signature = repo.signature()?;
+    let author = commit_signature(repo)?;
+    let committer = committer_signature(repo)?;
 
     let head_ref = repo.head().ok();
     let head_commit = head_ref.as_ref().and_then(|h| h.peel_to_commit().ok());
@@ -44,8 +54,8 @@ pub fn commit_all(repo: &Repository, message: &str, amend: bool) -> Result<(), g
 
     let new_commit_id = repo.commit(
         update_ref,
-        &signature,
-        &signature,
+        &author,
+        &committer,
         &final_message,
         &tree,
         &parent_refs,
@@ -61,12 +71,63 @@ pub fn commit_all(repo: &Repository, message: &str, amend: bool) -> Result<(), g
     Ok(())
 }
 
+/// Takes back the commit `gg save` just made, leaving its changes staged. Only the branch's
+/// latest reflog entry is undone, and only if it is a commit that hasn't reached the upstream.
+pub fn rollback_commit(repo: &Repository) -> Result<git2::Commit<'_>, Error> {
+    let head = repo.head()?;
+    let (Some(refname), Some(tip)) = (head.name(), head.target()) else {
+        return Err(Error::from_str("Nothing to roll back: not on a branch."));
+    };
+
+    let reflog = repo.reflog(refname)?;
+    let last = reflog
+        .get(0)
+        .filter(|entry| entry.id_new() == tip)
+        .filter(|entry| {
+            let message = entry.message().unwrap_or_default();
+            message.starts_with("commit:") || message == "gg: amend commit"
+        })
+        .ok_or_else(|| {
+            Error::from_str("Nothing to roll back: the branch's last change wasn't a commit.")
+        })?;
+    if last.id_old().is_zero() {
+        return Err(Error::from_str(
+            "Cannot roll back the first commit of a branch.",
+        ));
+    }
+
+    if let Some((remote, branch)) = current_upstream(repo)
+        && let Ok(upstream) = repo.refname_to_id(&format!("refs/remotes/{remote}/{branch}"))
+        && (upstream == tip || repo.graph_descendant_of(upstream, tip)?)
+    {
+        return Err(Error::from_str(&format!(
+            "The commit is already on '{remote}/{branch}'. Use 'gg undo --force' to take it back locally."
+        )));
+    }
+
+    let rolled_back = repo.find_commit(tip)?;
+    let previous = repo.find_object(last.id_old(), None)?;
+    repo.reset(&previous, git2::ResetType::Soft, None)?;
+    Ok(rolled_back)
+}
+
 /// Helper to Push changes to remote
 pub fn push(
     repo: &Repository,
     remote_name: &str,
     branch_name: &str,
     force: bool,
+) -> Result<(), Error> {
+    push_branch(repo, remote_name, branch_name, force, None)
+}
+
+/// Pushes a branch; with a `lease`, only while the remote branch still points to that commit
+fn push_branch(
+    repo: &Repository,
+    remote_name: &str,
+    branch_name: &str,
+    force: bool,
+    lease: Option<Oid>,
 ) -> Result<(), Error> {
     // Safety check: Never try to push a literal "HEAD" refspec
     if branch_name == "HEAD" {
@@ -79,60 +140,297 @@ pub fn push(
         return Ok(());
     }
 
-    let mut remote = repo.find_remote(remote_name)?;
+    let mut remote = open_remote(repo, remote_name)?;
     let mut push_opts = PushOptions::new();
-    push_opts.remote_callbacks(create_callbacks());
+
+    // Collect the remote's verdict on each ref, as libgit2 only errors on transport failures
+    let rejections = Rc::new(RefCell::new(Vec::new()));
+    let mut callbacks = create_callbacks(repo);
+    let r = rejections.clone();
+    callbacks.push_update_reference(move |refname, status| {
+        if let Some(reason) = status {
+            r.borrow_mut()
+                .push((refname.to_string(), reason.to_string()));
+        }
+        Ok(())
+    });
+
+    // The lease is checked against the remote's refs as the push negotiates them,
+    // so a push that lands between our fetch and this push is still caught
+    let lease_broken = Rc::new(Cell::new(false));
+    if let Some(expected) = lease {
+        let refname = format!("refs/heads/{branch_name}");
+        let broken = lease_broken.clone();
+        callbacks.push_negotiation(move |updates| {
+            if updates
+                .iter()
+                .any(|u| u.dst_refname() == Some(refname.as_str()) && u.src() != expected)
+            {
+                broken.set(true);
+                return Err(Error::from_str("stale info"));
+            }
+            Ok(())
+        });
+    }
+    push_opts.remote_callbacks(callbacks);
 
     let prefix = if force { "+" } else { "" };
     let refspec = format!("{prefix}refs/heads/{branch_name}:refs/heads/{branch_name}");
 
-    remote.push(&[&refspec], Some(&mut push_opts))?;
+    if let Err(e) = remote.push(&[&refspec], Some(&mut push_opts)) {
+        if lease_broken.get() {
+            return Err(push_error("stale info", branch_name));
+        }
+        if is_offline_error(&e) {
+            return Err(offline_error(&e, remote_name));
+        }
+        return Err(push_error(e.message(), branch_name));
+    }
+
+    if let Some((refname, reason)) = rejections.borrow().first() {
+        let refname = refname.strip_prefix("refs/heads/").unwrap_or(refname);
+        return Err(push_error(
+            &format!("'{refname}' was rejected by the remote: {reason}"),
+            branch_name,
+        ));
+    }
+
+    crate::journal::note_push(remote_name, branch_name);
+    clear_queued_push(repo, branch_name)?;
+    let mut branch = repo.find_branch(branch_name, BranchType::Local)?;
+
+    // Pushes through an ssh alias go via an anonymous remote, which can't update tracking refs
+    if remote.name().is_none()
+        && let Some(oid) = branch.get().target()
+    {
+        repo.reference(
+            &format!("refs/remotes/{remote_name}/{branch_name}"),
+            oid,
+            true,
+            "gg: push",
+        )?;
+    }
+
+    // First push of this branch: track what we just pushed, like 'git push -u'
+    if branch.upstream().is_err() {
+        branch.set_upstream(Some(&format!("{remote_name}/{branch_name}")))?;
+    }
 
     Ok(())
 }
 
-/// Helper to Pull (Fetch + Merge/FastForward)
+/// Builds a push error, with a hint on how to fix the usual rejection reasons
+fn push_error(reason: &str, branch_name: &str) -> Error {
+    let lower = reason.to_lowercase();
+    let hint = if lower.contains("non-fast-forward")
+        || lower.contains("fetch first")
+        || lower.contains("stale info")
+        || lower.contains("not present locally")
+    {
+        Some("The remote has new commits. Run 'gg pull' and try again.".to_string())
+    } else if lower.contains("protected") {
+        Some(format!(
+            "'{branch_name}' is protected on the remote. Save your work on a feature branch ('gg feature') and open a PR ('gg pr')."
+        ))
+    } else if lower.contains("hook") {
+        Some("A server-side hook refused the push; see the remote messages above.".to_string())
+    } else if lower.contains("permission") || lower.contains("denied") {
+        Some(
+            "You don't have write access to this remote. Check 'gg creds' and the remote URL."
+                .to_string(),
+        )
+    } else {
+        None
+    };
+
+    match hint {
+        Some(hint) => Error::from_str(&format!("Push failed: {reason}\nHint: {hint}")),
+        None => Error::from_str(&format!("Push failed: {reason}")),
+    }
+}
+
+/// Whether a fetch or push failed because the remote couldn't be reached at all,
+/// rather than refusing us (bad credentials, rejected refs...)
+pub fn is_offline_error(e: &Error) -> bool {
+    const SYMPTOMS: [&str; 7] = [
+        "failed to resolve address",
+        "could not resolve host",
+        "failed to connect",
+        "connection refused",
+        "timed out",
+        "network is unreachable",
+        "no route to host",
+    ];
+    let message = e.message().to_lowercase();
+    e.class() == ErrorClass::Net || SYMPTOMS.iter().any(|s| message.contains(s))
+}
+
+/// Explains a connect failure, keeping it classified as a network error
+pub(crate) fn offline_error(e: &Error, remote_name: &str) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Net,
+        format!(
+            "Can't reach '{remote_name}': {}\nHint: You seem to be offline. Use --offline to commit \
+             locally, and 'gg sync' to push once you're back online.",
+            e.message()
+        ),
+    )
+}
+
+/// Returns the remote and branch the current branch tracks (`branch.<name>.remote`/`merge`)
+pub fn current_upstream(repo: &Repository) -> Option<(String, String)> {
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    let refname = head.name()?;
+
+    let remote = repo.branch_upstream_remote(refname).ok()?;
+    let merge = repo.branch_upstream_merge(refname).ok()?;
+    let merge = merge.as_str()?;
+    let branch = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+
+    Some((remote.as_str()?.to_string(), branch.to_string()))
+}
+
+/// Force-pushes a branch, refusing if the remote branch no longer points to `expected`
+/// (usually the commit last fetched into `refs/remotes/<remote>/<branch>`)
+pub fn push_with_lease(
+    repo: &Repository,
+    remote_name: &str,
+    branch_name: &str,
+    expected: Oid,
+) -> Result<(), Error> {
+    push_branch(repo, remote_name, branch_name, true, Some(expected))
+}
+
+/// Fetches a remote using its configured refspecs, updating the remote-tracking refs
+/// (`refs/remotes/<remote>/*`). With `prune`, tracking refs of deleted remote branches
+/// are removed; otherwise the remote's `prune`/`fetch.prune` config decides.
+pub fn fetch(repo: &Repository, remote_name: &str, prune: bool) -> Result<(), Error> {
+    let mut remote = open_remote(repo, remote_name)?;
+    let mut fetch_opts = git2::FetchOptions::new();
+    fetch_opts.remote_callbacks(create_callbacks(repo));
+    if prune {
+        fetch_opts.prune(git2::FetchPrune::On);
+    }
+
+    // An empty refspec list means "use the refspecs from the remote's config",
+    // which an anonymous remote (see `open_remote`) doesn't have
+    let refspecs: Vec<String> = if remote.name().is_none() {
+        let named = repo.find_remote(remote_name)?;
+        named
+            .fetch_refspecs()?
+            .iter()
+            .flatten()
+            .map(str::to_string)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    remote.fetch(&refspecs, Some(&mut fetch_opts), Some("gg: fetch"))
+}
+
+/// Fetches every configured remote
+pub fn fetch_all(repo: &Repository, prune: bool) -> Result<(), Error> {
+    for name in repo.remotes()?.iter().flatten() {
+        show_progress(&format!("Fetching '{}'", name.bold()), || {
+            fetch(repo, name, prune)
+        })?;
+    }
+    Ok(())
+}
+
+/// Works out which remote branch a pull should merge: `branch_name` if given, otherwise the
+/// current branch's upstream, falling back to its namesake on the remote.
+fn resolve_pull_source(
+    repo: &Repository,
+    remote_name: &str,
+    branch_name: Option<&str>,
+) -> Option<(String, String)> {
+    if let Some(branch_name) = branch_name {
+        return Some((remote_name.to_string(), branch_name.to_string()));
+    }
+
+    if let Some(upstream) = current_upstream(repo) {
+        return Some(upstream);
+    }
+
+    let head = repo.head().ok()?;
+    let current = head.shorthand().filter(|_| head.is_branch())?;
+    repo.find_branch(&format!("{remote_name}/{current}"), BranchType::Remote)
+        .ok()
+        .map(|_| (remote_name.to_string(), current.to_string()))
+}
+
+/// Helper to Pull (Fetch + Merge/FastForward) into the current branch.
+/// `branch_name` is the remote branch to merge, or `None` for the current branch's upstream.
 /// Note: git2 does not have a "pull" command. We must Fetch, Analyze, then Merge.
-pub fn pull(repo: &Repository, remote_name: &str, branch_name: &str) -> Result<(), Error> {
+pub fn pull(repo: &Repository, remote_name: &str, branch_name: Option<&str>) -> Result<(), Error> {
+    if branch_name == Some("HEAD") {
+        // origin/HEAD is only the remote's default branch, which is rarely what was meant
+        return Err(Error::from_str(
+            "'HEAD' is not a branch to pull from. Leave out --from to pull the current \
+             branch's upstream, or name the remote branch.",
+        ));
+    }
+    let Some((remote_name, branch_name)) = resolve_pull_source(repo, remote_name, branch_name)
+    else {
+        // Nothing upstream yet (e.g. a branch that was never pushed)
+        return Ok(());
+    };
+    let (remote_name, branch_name) = (remote_name.as_str(), branch_name.as_str());
+
     if !has_remote(repo, remote_name) {
         return Ok(());
     }
 
-    // 1. Fetch
-    let mut remote = repo.find_remote(remote_name)?;
-    let mut fetch_opts = git2::FetchOptions::new();
-    fetch_opts.remote_callbacks(create_callbacks());
-
-    // Fetch specifically the branch we are interested in, or HEAD
-    remote.fetch(&[branch_name], Some(&mut fetch_opts), None)?;
+    // 1. Fetch, keeping the remote-tracking refs up to date
+    fetch(repo, remote_name, false).map_err(|e| {
+        if is_offline_error(&e) {
+            offline_error(&e, remote_name)
+        } else {
+            e
+        }
+    })?;
 
     // 2. Prepare for Merge Analysis
-    let fetch_head = repo.find_reference("FETCH_HEAD")?;
-    let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
+    let tracking_name = format!("refs/remotes/{remote_name}/{branch_name}");
+    let tracking_ref = repo.find_reference(&tracking_name).map_err(|_| {
+        Error::from_str(&format!(
+            "Branch '{branch_name}' was not found on '{remote_name}'"
+        ))
+    })?;
+    let fetch_commit = repo.reference_to_annotated_commit(&tracking_ref)?;
 
     let analysis = repo.merge_analysis(&[&fetch_commit])?;
 
     // 3. Act on Analysis
-    if analysis.0.is_fast_forward() {
-        // Fast-forward the branch
-        let refname = format!("refs/heads/{branch_name}");
-        match repo.find_reference(&refname) {
-            Ok(mut r) => {
-                let name = refname.clone();
-                let msg = format!(
-                    "Fast-Forward: Setting {} to id: {}",
-                    name,
-                    fetch_commit.id()
-                );
-                r.set_target(fetch_commit.id(), &msg)?;
-                repo.set_head(&name)?;
-                repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
-            }
-            Err(_) => {
-                // If checking HEAD and it's detached or similar, just checkout the commit
-                repo.set_head_detached(fetch_commit.id())?;
-            }
-        };
+    if analysis.0.is_unborn() {
+        // Nothing committed yet: start the current branch at the fetched commit
+        let head = repo.find_reference("HEAD")?;
+        let refname = head
+            .symbolic_target()
+            .ok_or_else(|| Error::from_str("HEAD does not point to a branch"))?;
+        checkout_keeping_changes(repo, &repo.find_object(fetch_commit.id(), None)?)?;
+        repo.reference(refname, fetch_commit.id(), false, "gg: initial pull")?;
+    } else if analysis.0.is_fast_forward() {
+        // Fast-forward the current branch, wherever we are pulling from. The files go first,
+        // so local changes in the way stop the pull before anything moves.
+        checkout_keeping_changes(repo, &repo.find_object(fetch_commit.id(), None)?)?;
+        let mut head = repo.head()?;
+        if head.is_branch() {
+            let msg = format!(
+                "Fast-Forward: Setting {} to id: {}",
+                head.name().unwrap_or("HEAD"),
+                fetch_commit.id()
+            );
+            head.set_target(fetch_commit.id(), &msg)?;
+        } else {
+            // Detached HEAD: just move to the fetched commit
+            repo.set_head_detached(fetch_commit.id())?;
+        }
     } else if analysis.0.is_up_to_date() {
         // Do nothing
     } else if analysis.0.is_normal() {
@@ -155,6 +453,22 @@ pub fn pull(repo: &Repository, remote_name: &str, branch_name: &str) -> Result<(
         )?;
 
         if index.has_conflicts() {
+            // Resolving writes the local version of each conflicted file over the working one
+            let edited: Vec<String> = index
+                .conflicts()?
+                .filter_map(|c| {
+                    c.ok()?
+                        .our
+                        .map(|e| String::from_utf8_lossy(&e.path).into_owned())
+                })
+                .filter(|path| {
+                    repo.status_file(Path::new(path))
+                        .is_ok_and(|s| s != git2::Status::CURRENT)
+                })
+                .collect();
+            if !edited.is_empty() {
+                return Err(local_changes_error(&edited));
+            }
             resolve_conflicts_ours(repo, &mut index)?;
             println!("\nYou can manually merge the '.theirs' files at any time.");
         }
@@ -163,28 +477,64 @@ pub fn pull(repo: &Repository, remote_name: &str, branch_name: &str) -> Result<(
         // contain the 'ours' versions that we added back to the index.
         let tree_oid = index.write_tree_to(repo)?;
         let tree = repo.find_tree(tree_oid)?;
+        checkout_keeping_changes(repo, tree.as_object())?;
 
-        let signature = repo.signature()?;
+        let author = commit_signature(repo)?;
+        let committer = committer_signature(repo)?;
         let head_shorthand = repo.head()?.shorthand().unwrap_or("HEAD").to_string();
-        let msg =
-            format!("Merge remote-tracking branch 'origin/{head_shorthand}' into {head_shorthand}");
+        let msg = format!(
+            "Merge remote-tracking branch '{remote_name}/{branch_name}' into {head_shorthand}"
+        );
 
         repo.commit(
             Some("HEAD"),
-            &signature,
-            &signature,
+            &author,
+            &committer,
             &msg,
             &tree,
             &[&our_commit_obj, &their_commit_obj],
         )?;
-
-        // Finally, update the working directory to reflect the new merge commit
-        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
     }
 
     Ok(())
 }
 
+/// Updates the working directory from HEAD to `target` without touching local changes.
+/// Files that the update would overwrite, because they were edited locally, stop it before
+/// anything is written.
+fn checkout_keeping_changes(repo: &Repository, target: &git2::Object) -> Result<(), Error> {
+    let in_the_way = RefCell::new(Vec::new());
+    let mut checkout = git2::build::CheckoutBuilder::new();
+    checkout
+        .safe()
+        .notify_on(git2::CheckoutNotificationType::CONFLICT)
+        .notify(|_, path, _, _, _| {
+            if let Some(path) = path {
+                in_the_way
+                    .borrow_mut()
+                    .push(path.to_string_lossy().into_owned());
+            }
+            true
+        });
+
+    let result = repo.checkout_tree(target, Some(&mut checkout));
+    drop(checkout);
+    match result {
+        Err(e) if e.code() == ErrorCode::Conflict => {
+            Err(local_changes_error(&in_the_way.into_inner()))
+        }
+        other => other,
+    }
+}
+
+fn local_changes_error(paths: &[String]) -> Error {
+    Error::from_str(&format!(
+        "Pulling would overwrite your local changes to {}. Save or stash them \
+         (or pass --autostash) and try again.",
+        paths.join(", ")
+    ))
+}
+
 // --- Helper Functions ---
 
 fn find_theirs_files(
@@ -210,7 +560,21 @@ fn find_theirs_files(
     Ok(())
 }
 
+/// Bits of `IndexEntry::flags` holding the conflict stage (0 for resolved entries)
+const STAGE_MASK: u16 = 0x3000;
+
 fn resolve_conflicts_ours(repo: &Repository, index: &mut git2::Index) -> Result<(), Error> {
+    resolve_conflicts_keeping_local(repo, index, true)
+}
+
+/// Resolves every conflict in `index` with the local version, saving the remote one next to
+/// it as a `.theirs` file for `gg resolve`. `local_is_ours` says which side of the conflict
+/// is local: "ours" in a merge, but "theirs" while rebasing local commits onto the remote.
+pub(crate) fn resolve_conflicts_keeping_local(
+    repo: &Repository,
+    index: &mut git2::Index,
+    local_is_ours: bool,
+) -> Result<(), Error> {
     let workdir = repo
         .workdir()
         .ok_or_else(|| Error::from_str("Repository has no workdir"))?;
@@ -222,9 +586,14 @@ fn resolve_conflicts_ours(repo: &Repository, index: &mut git2::Index) -> Result<
 
     for conflict in conflicts {
         let our_path_str: String;
+        let (ours, theirs) = if local_is_ours {
+            (conflict.our, conflict.their)
+        } else {
+            (conflict.their, conflict.our)
+        };
 
         // First, resolve the conflict in the index by choosing our version.
-        if let Some(our) = &conflict.our {
+        if let Some(our) = &ours {
             let path_bytes = &our.path;
             our_path_str = String::from_utf8_lossy(path_bytes).to_string();
             let path = Path::new(&our_path_str);
@@ -239,13 +608,30 @@ fn resolve_conflicts_ours(repo: &Repository, index: &mut git2::Index) -> Result<
             std::fs::write(&full_path, blob.content())
                 .map_err(|e| Error::from_str(&format!("Failed to write file: {e}")))?;
 
-            index.add_path(path)?;
+            // Stage our entry directly: merge indexes aren't backed by the workdir,
+            // so `add_path` can't be used on them
+            let entry = git2::IndexEntry {
+                ctime: our.ctime,
+                mtime: our.mtime,
+                dev: our.dev,
+                ino: our.ino,
+                mode: our.mode,
+                uid: our.uid,
+                gid: our.gid,
+                file_size: our.file_size,
+                id: our.id,
+                flags: our.flags & !STAGE_MASK,
+                flags_extended: our.flags_extended,
+                path: our.path.clone(),
+            };
+            index.conflict_remove(path)?;
+            index.add(&entry)?;
         } else {
             continue;
         }
 
         // Second, save the 'theirs' version to a file.
-        if let Some(their) = &conflict.their {
+        if let Some(their) = &theirs {
             let blob = repo.find_blob(their.id)?;
             let content = blob.content();
             let theirs_filename = format!("{our_path_str}.theirs");
@@ -331,34 +717,112 @@ pub fn resolve(repo: &Repository, cleanup: bool) -> Result<(), Error> {
     Ok(())
 }
 
+/// When `gg feature` publishes a new branch, read from `gg.feature.pushOnCreate`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PushOnCreate {
+    /// Push the (still empty) branch right away
+    Always,
+    /// Wait for the first `gg save` to create the upstream branch
+    Lazy,
+}
+
+impl PushOnCreate {
+    pub fn from_config(repo: &Repository) -> Result<Self, Error> {
+        match gg_config_str(repo, "gg.feature.pushOnCreate").as_deref() {
+            None | Some("always" | "true" | "yes" | "on") => Ok(PushOnCreate::Always),
+            Some("lazy" | "false" | "no" | "off") => Ok(PushOnCreate::Lazy),
+            Some(other) => Err(Error::from_str(&format!(
+                "Invalid gg.feature.pushOnCreate '{other}': expected 'always' or 'lazy'"
+            ))),
+        }
+    }
+}
+
+/// Fetches a single branch from a remote, updating its remote-tracking ref
+fn fetch_remote_branch(repo: &Repository, remote_name: &str, branch: &str) -> Result<(), Error> {
+    let mut remote = open_remote(repo, remote_name)?;
+    let mut fetch_opts = git2::FetchOptions::new();
+    fetch_opts.remote_callbacks(create_callbacks(repo));
+    let refspec = format!("refs/heads/{branch}:refs/remotes/{remote_name}/{branch}");
+    remote.fetch(&[&refspec], Some(&mut fetch_opts), None)
+}
+
+/// Resolves the `--base` of a new feature locally (branch, tag, `origin/x`, SHA, `HEAD~3`...).
+/// The network is only used when the base names a remote branch.
+fn resolve_base<'r>(repo: &'r Repository, base: &str) -> Result<git2::Commit<'r>, Error> {
+    if let Ok(branch) = repo.find_branch(base, BranchType::Local) {
+        return branch.get().peel_to_commit();
+    }
+
+    // "origin/x" refers to a remote branch: refresh it, falling back to what we have
+    if let Some((remote_name, branch)) = base.split_once('/')
+        && has_remote(repo, remote_name)
+    {
+        let fetched = show_progress(&format!("Fetching latest of '{}'", base.bold()), || {
+            fetch_remote_branch(repo, remote_name, branch)
+        });
+        if let Err(e) = fetched {
+            eprintln!(
+                "Could not fetch '{base}' ({}), using the local copy",
+                e.message()
+            );
+        }
+        return repo
+            .find_branch(base, BranchType::Remote)?
+            .get()
+            .peel_to_commit();
+    }
+
+    if let Ok(object) = repo.revparse_single(base) {
+        return object.peel_to_commit();
+    }
+
+    // Last resort: a branch that only exists on origin
+    if has_remote(repo, "origin") {
+        show_progress(&format!("Fetching '{}' from origin", base.bold()), || {
+            fetch_remote_branch(repo, "origin", base)
+        })?;
+        return repo
+            .find_branch(&format!("origin/{base}"), BranchType::Remote)?
+            .get()
+            .peel_to_commit();
+    }
+
+    Err(Error::from_str(&format!(
+        "Could not find '{base}' as a branch, tag or commit"
+    )))
+}
+
+/// Creates (or switches to) a feature branch and publishes it. `offline` skips the pull and
+/// queues the push for `gg sync`.
 pub fn create_feature_branch(
     repo: &git2::Repository,
     name: &str,
     base: Option<String>,
+    offline: bool,
 ) -> Result<(), Error> {
+    // Catch a bad setting before anything is created
+    let push_on_create = PushOnCreate::from_config(repo)?;
+
     // 1. Determine base commit
     let (base_commit, base_name) = match base {
-        Some(base_branch_name) => {
-            let commit = show_progress(
-                &format!("Fetching latest of '{}'", base_branch_name.bold()),
-                || {
-                    let mut remote = repo.find_remote("origin")?;
-                    let mut fetch_opts = git2::FetchOptions::new();
-                    fetch_opts.remote_callbacks(create_callbacks());
-                    remote.fetch(&[&base_branch_name], Some(&mut fetch_opts), None)?;
-
-                    let fetch_head = repo.find_reference("FETCH_HEAD")?;
-                    let commit = repo.reference_to_annotated_commit(&fetch_head)?.id();
-                    repo.find_commit(commit)
-                },
-            )?;
-            println!("Basing new feature on '{}'", base_branch_name.bold());
-            (commit, base_branch_name)
+        Some(base_name) => {
+            let commit = resolve_base(repo, &base_name)?;
+            println!("Basing new feature on '{}'", base_name.bold());
+            (commit, base_name)
         }
         None => {
-            show_progress("Syncing current branch", || pull(repo, "origin", "HEAD"))?;
-            let commit = repo.head()?.peel_to_commit()?;
-            (commit, "HEAD".to_string())
+            if !offline {
+                show_progress("Syncing current branch", || pull(repo, "origin", None))?;
+            }
+            let head = repo.head()?;
+            let commit = head.peel_to_commit()?;
+            // Branching off another branch stacks on top of it
+            let head_name = match head.shorthand() {
+                Some(name) if head.is_branch() => name.to_string(),
+                _ => "HEAD".to_string(),
+            };
+            (commit, head_name)
         }
     };
 
@@ -380,7 +844,14 @@ pub fn create_feature_branch(
             name.bold(),
             base_name.bold()
         );
-        repo.branch(name, &base_commit, false)?
+        let branch = repo.branch(name, &base_commit, false)?;
+
+        // Remember what we stacked on, so 'gg restack' and 'gg pr' can follow it
+        let is_local_branch = repo.find_branch(&base_name, BranchType::Local).is_ok();
+        if is_local_branch && base_name != main_branch_name(repo) {
+            set_branch_parent(repo, name, &base_name, base_commit.id())?;
+        }
+        branch
     };
 
     // 3. Switch HEAD
@@ -398,7 +869,21 @@ pub fn create_feature_branch(
     }
 
     // 4. Push upstream
-    show_progress("Pushing upstream", || push(repo, "origin", name, false))?;
+    if !has_remote(repo, "origin") {
+        return Ok(());
+    }
+    match push_on_create {
+        PushOnCreate::Always if offline => {
+            queue_push(repo, name, false)?;
+            println!("Offline: 'gg sync' will push the branch once you're back online");
+        }
+        PushOnCreate::Always => {
+            show_progress("Pushing upstream", || push(repo, "origin", name, false))?;
+        }
+        PushOnCreate::Lazy => {
+            println!("Branch will be pushed on your first 'gg save'");
+        }
+    }
 
     Ok(())
 }
@@ -410,11 +895,7 @@ pub fn done(repo: &Repository, no_clean: bool, confirm_deletion: bool) -> Result
         .ok_or_else(|| Error::from_str("Not on a valid branch"))?
         .to_string();
 
-    let main_branch = if repo.find_branch("main", BranchType::Local).is_ok() {
-        "main"
-    } else {
-        "master"
-    };
+    let main_branch = main_branch_name(repo);
 
     if current_branch_name == main_branch {
         println!("Already on {main_branch}, nothing to finalize.");
@@ -427,7 +908,7 @@ pub fn done(repo: &Repository, no_clean: bool, confirm_deletion: bool) -> Result
     })?;
 
     show_progress(&format!("Pulling {main_branch}"), || {
-        pull(repo, "origin", main_branch)
+        pull(repo, "origin", Some(main_branch))
     })?;
 
     if !no_clean {
@@ -464,3 +945,115 @@ pub fn done(repo: &Repository, no_clean: bool, confirm_deletion: bool) -> Result
 
     Ok(())
 }
+
+/// How `gg features` orders the branch dashboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BranchSort {
+    /// Alphabetical by branch name
+    Name,
+    /// Most recently committed first
+    Date,
+    /// Most commits ahead of upstream first
+    Ahead,
+    /// Most commits behind upstream first
+    Behind,
+}
+
+/// A summary of a local branch, as shown by `gg features`
+#[derive(Debug, Serialize)]
+pub struct BranchInfo {
+    pub name: String,
+    pub is_head: bool,
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    /// Unix timestamp of the last commit
+    pub last_commit_time: i64,
+    pub last_commit_author: String,
+    pub merged: bool,
+    pub pending_conflicts: bool,
+}
+
+/// Checks whether a tree contains any committed '.theirs' files
+fn tree_has_theirs_files(tree: &git2::Tree) -> bool {
+    let mut found = false;
+    _ = tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+        if entry.name().is_some_and(|n| n.ends_with(".theirs")) {
+            found = true;
+            git2::TreeWalkResult::Abort
+        } else {
+            git2::TreeWalkResult::Ok
+        }
+    });
+    found
+}
+
+/// Collects the status of every local branch
+pub fn branch_dashboard(repo: &Repository, sort: BranchSort) -> Result<Vec<BranchInfo>, Error> {
+    let main_branch = main_branch_name(repo);
+    let main_oid = repo
+        .find_branch(main_branch, BranchType::Local)
+        .ok()
+        .and_then(|b| b.get().target());
+
+    let mut infos = Vec::new();
+    for b in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = b?;
+        let name = branch.name()?.unwrap_or("HEAD").to_string();
+        let commit = branch.get().peel_to_commit()?;
+        let is_head = branch.is_head();
+
+        let (upstream, ahead, behind) = match branch.upstream() {
+            Ok(up) => {
+                let up_name = up.name()?.map(str::to_string);
+                let (ahead, behind) = match up.get().target() {
+                    Some(up_oid) => repo.graph_ahead_behind(commit.id(), up_oid)?,
+                    None => (0, 0),
+                };
+                (up_name, ahead, behind)
+            }
+            Err(_) => (None, 0, 0),
+        };
+
+        let merged = match main_oid {
+            Some(main_oid) if name != main_branch => {
+                main_oid == commit.id() || repo.graph_descendant_of(main_oid, commit.id())?
+            }
+            _ => false,
+        };
+
+        // The working tree belongs to HEAD, so look there for uncommitted '.theirs' files too
+        let pending_conflicts = if is_head {
+            let mut theirs_files = Vec::new();
+            if let Some(workdir) = repo.workdir() {
+                find_theirs_files(workdir, &mut theirs_files).map_err(|e| {
+                    Error::from_str(&format!("Error scanning for conflict files: {e}"))
+                })?;
+            }
+            !theirs_files.is_empty()
+        } else {
+            tree_has_theirs_files(&commit.tree()?)
+        };
+
+        infos.push(BranchInfo {
+            name,
+            is_head,
+            upstream,
+            ahead,
+            behind,
+            last_commit_time: commit.time().seconds(),
+            last_commit_author: commit.author().name().unwrap_or("unknown").to_string(),
+            merged,
+            pending_conflicts,
+        });
+    }
+
+    match sort {
+        BranchSort::Name => infos.sort_by(|a, b| a.name.cmp(&b.name)),
+        BranchSort::Date => infos.sort_by_key(|b| std::cmp::Reverse(b.last_commit_time)),
+        BranchSort::Ahead => infos.sort_by_key(|b| std::cmp::Reverse(b.ahead)),
+        BranchSort::Behind => infos.sort_by_key(|b| std::cmp::Reverse(b.behind)),
+    }
+
+    Ok(infos)
+}
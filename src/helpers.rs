@@ -1,14 +1,56 @@
 use git_url_parse::GitUrl;
 use git_url_parse::types::provider::{AzureDevOpsProvider, GenericProvider, GitLabProvider};
-use git2::{CertificateCheckStatus, Config, Cred, Error, RemoteCallbacks, Repository};
+use git2::{Cred, Error, RemoteCallbacks, Repository};
 use owo_colors::OwoColorize;
-use std::cell::Cell;
 use std::io::Write;
+use std::path::PathBuf;
+
+use crate::https::HttpsAuth;
+use crate::progress::{self, TransferDisplay};
+use crate::security::check_certificate;
+use crate::ssh::SshAuth;
 
 pub fn has_remote(repo: &Repository, name: &str) -> bool {
     repo.find_remote(name).is_ok()
 }
 
+/// Reads a gg setting from git config (local, then global, then system)
+pub fn gg_config_str(repo: &Repository, key: &str) -> Option<String> {
+    repo.config()
+        .and_then(|mut c| c.snapshot())
+        .and_then(|c| c.get_string(key))
+        .ok()
+}
+
+/// The user's home directory (`$HOME`)
+pub fn home_dir() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| ".".into()))
+}
+
+/// Returns the name of the repo's main branch ("main" if it exists, "master" otherwise)
+pub fn main_branch_name(repo: &Repository) -> &'static str {
+    if repo.find_branch("main", git2::BranchType::Local).is_ok() {
+        "main"
+    } else {
+        "master"
+    }
+}
+
+/// Formats a duration in seconds as a human readable age (e.g. "3 days ago")
+pub fn format_age(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    let (value, unit) = match seconds {
+        s if s < 60 => return "just now".to_string(),
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86400 => (s / 3600, "hour"),
+        s if s < 86400 * 30 => (s / 86400, "day"),
+        s if s < 86400 * 365 => (s / (86400 * 30), "month"),
+        s => (s / (86400 * 365), "year"),
+    };
+    let plural = if value == 1 { "" } else { "s" };
+    format!("{value} {unit}{plural} ago")
+}
+
 /// Analyzes the diff to suggest a Conventional Commit prefix
 pub fn generate_conventional_message(repo: &Repository) -> Result<String, git2::Error> {
     let index = repo.index()?;
@@ -79,9 +121,15 @@ pub fn generate_conventional_message(repo: &Repository) -> Result<String, git2::
 }
 
 /// Creates remote callbacks for SSH/Credential handling
-pub fn create_callbacks() -> RemoteCallbacks<'static> {
+pub fn create_callbacks(repo: &Repository) -> RemoteCallbacks<'static> {
     let mut callbacks = RemoteCallbacks::new();
-    let attempt = Cell::new(0);
+
+    // Like git, GIT_SSH_COMMAND wins over core.sshCommand
+    let ssh_command = std::env::var("GIT_SSH_COMMAND")
+        .ok()
+        .or_else(|| gg_config_str(repo, "core.sshCommand"));
+    let mut ssh = SshAuth::new(ssh_command);
+    let mut https = HttpsAuth::new();
 
     callbacks.credentials(move |url, username_from_url, allowed_types| {
         // Heuristic to check for local file paths, which don't need credentials.
@@ -89,114 +137,43 @@ pub fn create_callbacks() -> RemoteCallbacks<'static> {
             return Err(Error::from_str("No credentials for local file path"));
         }
 
-        let count = attempt.get();
-        attempt.set(count + 1);
-
-        // Stop the infinite loop if we've tried agent and disk keys, and failed.
-        if count > 2 {
-            return Err(Error::from_str(
-                "Authentication failed: tried agent and default SSH keys.",
-            ));
+        // SSH URLs without a user first ask who to log in as
+        if allowed_types.contains(git2::CredentialType::USERNAME) {
+            return Cred::username(&ssh.username(url, username_from_url));
         }
 
-        let user = username_from_url.unwrap_or("git");
-
         if allowed_types.contains(git2::CredentialType::SSH_KEY) {
-            if count == 0 {
-                return Cred::ssh_key_from_agent(user);
-            } else {
-                // Fallback to common disk keys
-                let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
-                let dot_ssh = std::path::Path::new(&home).join(".ssh");
-
-                for key_name in ["id_ed25519", "id_rsa", "id_ecdsa"] {
-                    let key_path = dot_ssh.join(key_name);
-                    if key_path.exists() {
-                        return Cred::ssh_key(user, None, &key_path, None);
-                    }
-                }
-            }
+            return ssh.next_key(url, username_from_url);
         }
 
-        // If it's HTTPS, this usually pops a helper or fails for manual token entry
+        // HTTPS: tokens from the environment or credential files, then git's helper
         if allowed_types.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
-            return Cred::credential_helper(&Config::open_default()?, url, username_from_url);
+            return https.next_credential(url, username_from_url);
         }
 
         Err(Error::from_str("No valid authentication methods found"))
     });
 
-    callbacks.certificate_check(|_cert, _host| Ok(CertificateCheckStatus::CertificateOk));
+    // Live transfer progress
+    let display = TransferDisplay::new();
+    let d = display.clone();
+    callbacks.transfer_progress(move |stats| {
+        d.fetch(&stats);
+        true
+    });
+    let d = display.clone();
+    callbacks.push_transfer_progress(move |current, total, bytes| d.push(current, total, bytes));
+    let d = display.clone();
+    callbacks.pack_progress(move |stage, current, total| d.pack(stage, current, total));
+    callbacks.sideband_progress(move |data| {
+        display.sideband(data);
+        true
+    });
+
+    callbacks.certificate_check(check_certificate);
     callbacks
 }
 
-pub fn sync_unrelated_histories(repo: &Repository, remote_name: &str) -> Result<(), Error> {
-    let mut remote = repo.find_remote(remote_name)?;
-    let mut fetch_opts = git2::FetchOptions::new();
-    fetch_opts.remote_callbacks(create_callbacks());
-
-    // Fetch to see what the remote has
-    remote.fetch(
-        &["refs/heads/*:refs/remotes/origin/*"],
-        Some(&mut fetch_opts),
-        None,
-    )?;
-
-    // Determine local branch name (usually 'main' or 'master')
-    let local_branch_name = repo
-        .head()
-        .map(|h| h.shorthand().unwrap_or("main").to_string())
-        .unwrap_or_else(|_| "main".to_string());
-
-    let remote_ref_name = format!("refs/remotes/{remote_name}/{local_branch_name}");
-
-    if let Ok(remote_ref) = repo.find_reference(&remote_ref_name) {
-        let remote_commit_annotated = repo.reference_to_annotated_commit(&remote_ref)?;
-        // To get the actual Commit object:
-        let remote_commit_actual = repo.find_commit(remote_commit_annotated.id())?;
-
-        match repo.head() {
-            Ok(head) => {
-                let local_commit_annotated = repo.reference_to_annotated_commit(&head)?;
-                if local_commit_annotated.id() != remote_commit_annotated.id() {
-                    println!("--- Rebasing local work onto {remote_ref_name} ---");
-                    let mut rebase =
-                        repo.rebase(None, Some(&remote_commit_annotated), None, None)?;
-
-                    while let Some(op) = rebase.next() {
-                        op?;
-                        if repo.index()?.has_conflicts() {
-                            // Abort the rebase so the repo isn't left in a messy state
-                            rebase.abort()?;
-                            return Err(Error::from_str(
-                                "Conflict! Rebase aborted. Resolve manually.",
-                            ));
-                        }
-                        let sig = repo.signature()?;
-                        rebase.commit(None, &sig, None)?;
-                    }
-                    rebase.finish(None)?;
-                }
-            }
-            Err(_) => {
-                println!("--- Initializing local branch from remote ---");
-                // Use the actual commit object found via ID
-                repo.branch(&local_branch_name, &remote_commit_actual, false)?;
-                repo.set_head(&format!("refs/heads/{local_branch_name}"))?;
-                repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
-            }
-        }
-
-        // Link the branches for future 'Save' calls
-        let mut branch = repo.find_branch(&local_branch_name, git2::BranchType::Local)?;
-        branch.set_upstream(Some(&format!("{remote_name}/{local_branch_name}")))?;
-        println!("--- Tracking relationship established ---");
-    } else {
-        println!("--- Remote is empty. Ready for your first 'Save'. ---");
-    }
-
-    Ok(())
-}
 pub fn configure_git(
     repo: &Repository,
     name: &str,
@@ -217,6 +194,174 @@ pub fn configure_git(
     Ok(())
 }
 
+/// Environment variables that override the identity from git config, as in git
+const IDENTITY_ENV: [(&str, &str); 2] = [
+    ("user.name", "GIT_AUTHOR_NAME"),
+    ("user.email", "GIT_AUTHOR_EMAIL"),
+];
+
+/// One identity setting and where its value comes from
+#[derive(Debug, PartialEq, Eq)]
+pub struct IdentityValue {
+    pub key: &'static str,
+    pub value: Option<String>,
+    /// "env", "local", "global", "system"... or `None` when unset
+    pub scope: Option<String>,
+}
+
+fn scope_name(level: git2::ConfigLevel) -> &'static str {
+    match level {
+        git2::ConfigLevel::Local | git2::ConfigLevel::Worktree => "local",
+        git2::ConfigLevel::Global | git2::ConfigLevel::XDG => "global",
+        git2::ConfigLevel::System | git2::ConfigLevel::ProgramData => "system",
+        git2::ConfigLevel::App | git2::ConfigLevel::Highest => "app",
+    }
+}
+
+/// Reads user.name and user.email, with the scope each value comes from
+pub fn identity_sources(repo: &Repository) -> Result<Vec<IdentityValue>, Error> {
+    let config = repo.config()?.snapshot()?;
+    let mut values = Vec::new();
+
+    for (key, env) in IDENTITY_ENV {
+        let value = match std::env::var(env) {
+            Ok(value) if !value.is_empty() => IdentityValue {
+                key,
+                value: Some(value),
+                scope: Some(format!("env: {env}")),
+            },
+            _ => match config.get_entry(key) {
+                Ok(entry) => IdentityValue {
+                    key,
+                    value: entry.value().map(str::to_string),
+                    scope: Some(scope_name(entry.level()).to_string()),
+                },
+                Err(_) => IdentityValue {
+                    key,
+                    value: None,
+                    scope: None,
+                },
+            },
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/// The identity settings that are missing, e.g. `["user.email"]`
+pub fn missing_identity(repo: &Repository) -> Result<Vec<&'static str>, Error> {
+    Ok(identity_sources(repo)?
+        .into_iter()
+        .filter(|v| v.value.as_deref().is_none_or(str::is_empty))
+        .map(|v| v.key)
+        .collect())
+}
+
+fn no_identity_error(missing: &[&str]) -> Error {
+    Error::from_str(&format!(
+        "No commit identity: {} not set. Run 'gg creds \"Your Name\" \"you@example.com\"' first.",
+        missing.join(" and ")
+    ))
+}
+
+/// The author to commit with: the environment overrides git config, like in git
+pub fn commit_signature(repo: &Repository) -> Result<git2::Signature<'static>, Error> {
+    let missing = missing_identity(repo)?;
+    if !missing.is_empty() {
+        return Err(no_identity_error(&missing));
+    }
+
+    let values = identity_sources(repo)?;
+    let value = |key: &str| {
+        values
+            .iter()
+            .find(|v| v.key == key)
+            .and_then(|v| v.value.clone())
+            .unwrap_or_default()
+    };
+    git2::Signature::now(&value("user.name"), &value("user.email"))
+}
+
+/// The committer to commit with: `GIT_COMMITTER_NAME`/`GIT_COMMITTER_EMAIL` override git
+/// config, independently of the author's variables, like in git
+pub fn committer_signature(repo: &Repository) -> Result<git2::Signature<'static>, Error> {
+    let config = repo.config()?.snapshot()?;
+    let value = |key: &str, env: &str| match std::env::var(env) {
+        Ok(value) if !value.is_empty() => Some(value),
+        _ => config.get_string(key).ok().filter(|v| !v.is_empty()),
+    };
+
+    let name = value("user.name", "GIT_COMMITTER_NAME");
+    let email = value("user.email", "GIT_COMMITTER_EMAIL");
+    if let (Some(name), Some(email)) = (&name, &email) {
+        return git2::Signature::now(name, email);
+    }
+
+    let missing: Vec<&str> = [
+        ("user.name", name.is_none()),
+        ("user.email", email.is_none()),
+    ]
+    .into_iter()
+    .filter_map(|(key, missing)| missing.then_some(key))
+    .collect();
+    Err(no_identity_error(&missing))
+}
+
+/// Asks for a name and email on the terminal and saves them with `configure_git`.
+/// Returns false when there is no terminal or the user declines.
+pub fn prompt_identity(repo: &Repository, missing: &[&str]) -> Result<bool, Error> {
+    use std::io::IsTerminal;
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
+
+    let ask = |question: &str| -> Option<String> {
+        print!("{question}");
+        _ = std::io::stdout().flush();
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).ok()?;
+        Some(input.trim().to_string())
+    };
+
+    println!(
+        "{}: Git doesn't know who you are ({} not set).",
+        "Note".yellow(),
+        missing.join(" and ")
+    );
+    let answer = ask("Set your identity now? [Y/n]: ").unwrap_or_default();
+    if matches!(answer.to_lowercase().as_str(), "n" | "no") {
+        return Ok(false);
+    }
+
+    let current = identity_sources(repo)?;
+    let current = |key: &str| {
+        current
+            .iter()
+            .find(|v| v.key == key)
+            .and_then(|v| v.value.clone())
+            .filter(|v| !v.is_empty())
+    };
+    let name = match current("user.name") {
+        Some(name) => name,
+        None => ask("Name: ").unwrap_or_default(),
+    };
+    let email = match current("user.email") {
+        Some(email) => email,
+        None => ask("Email: ").unwrap_or_default(),
+    };
+    if name.is_empty() || email.is_empty() {
+        return Ok(false);
+    }
+
+    let global = ask("Use it for all your repos (~/.gitconfig)? [Y/n]: ").unwrap_or_default();
+    let global = !matches!(global.to_lowercase().as_str(), "n" | "no");
+    configure_git(repo, &name, &email, global)?;
+
+    let scope = if global { "globally" } else { "locally" };
+    println!("--- Configured {scope} as {name} <{email}> ---");
+    Ok(true)
+}
+
 pub fn is_dirty(repo: &Repository) -> Result<bool, Error> {
     let mut status_options = git2::StatusOptions::new();
     // We include untracked files because they can cause conflicts during
@@ -236,18 +381,54 @@ where
 {
     print!("{message}... ");
     std::io::stdout().flush().unwrap();
-    match action() {
+    progress::begin_step(message);
+    let result = action();
+    progress::end_step();
+    progress::finish_step(message, result.is_ok());
+    match result {
         Ok(result) => {
             println!("{}", "Done".green());
+            print_remote_messages();
             Ok(result)
         }
         Err(e) => {
             println!("{}", "Error".red());
+            print_remote_messages();
             Err(e)
         }
     }
 }
 
+/// Turns the error of a failed `gg save` into a report of the steps that went through,
+/// with the way out that fits where it stopped
+pub fn save_failure(error: Error, steps: &[(String, bool)]) -> Error {
+    let mut report = format!("{}\n\nSave stopped partway:", error.message());
+    for (step, succeeded) in steps {
+        let mark = if *succeeded {
+            "✓".green().to_string()
+        } else {
+            "✗".red().to_string()
+        };
+        report.push_str(&format!("\n  {mark} {step}"));
+    }
+
+    let committed = steps.iter().any(|(step, ok)| step == "Committing" && *ok);
+    report.push_str(if committed {
+        "\n\nYour commit is saved locally. Run 'gg push' to retry the push, \
+         or 'gg save --rollback' to take the commit back and keep its changes staged."
+    } else {
+        "\n\nNothing was committed; your changes are still in the working directory."
+    });
+    Error::from_str(&report)
+}
+
+/// Prints what the remote had to say during the last transfer (hook output, PR links...)
+pub fn print_remote_messages() {
+    for line in progress::take_remote_messages() {
+        println!("  {} {line}", "remote:".dimmed());
+    }
+}
+
 pub fn get_pr_link(repo: &Repository) -> Option<String> {
     // 1. Get the current branch name (e.g., "feature/my-new-thing")
     let head = if let Ok(head) = repo.head() {
@@ -257,6 +438,9 @@ pub fn get_pr_link(repo: &Repository) -> Option<String> {
     };
     let branch_name = head.shorthand().unwrap_or("main");
 
+    // Stacked branches should be reviewed against their parent, not main
+    let base = crate::stack::branch_parent(repo, branch_name);
+
     // 2. Get the remote URL (usually "origin")
     let remote = if let Ok(remote) = repo.find_remote("origin") {
         remote
@@ -285,8 +469,12 @@ pub fn get_pr_link(repo: &Repository) -> Option<String> {
             };
             let path = format!("{}/{}", provider_info.owner(), provider_info.repo()); // owner/repo
 
-            // GitHub format: https://github.com/OWNER/REPO/compare/BRANCH?expand=1
-            format!("https://github.com/{path}/compare/{branch_name}?expand=1")
+            // GitHub format: https://github.com/OWNER/REPO/compare/[BASE...]BRANCH?expand=1
+            let range = match &base {
+                Some(base) => format!("{base}...{branch_name}"),
+                None => branch_name.to_string(),
+            };
+            format!("https://github.com/{path}/compare/{range}?expand=1")
         }
         "gitlab.com" => {
             let provider_info: GitLabProvider = if let Ok(info) = parsed.provider_info() {
@@ -297,8 +485,12 @@ pub fn get_pr_link(repo: &Repository) -> Option<String> {
             let path = format!("{}/{}", provider_info.owner(), provider_info.repo()); // owner/repo
 
             // GitLab format: https://gitlab.com/OWNER/REPO/-/merge_requests/new?merge_request[source_branch]=BRANCH
+            let target = match &base {
+                Some(base) => format!("&merge_request[target_branch]={base}"),
+                None => String::new(),
+            };
             format!(
-                "https://gitlab.com/{path}/-/merge_requests/new?merge_request[source_branch]={branch_name}"
+                "https://gitlab.com/{path}/-/merge_requests/new?merge_request[source_branch]={branch_name}{target}"
             )
         }
         "bitbucket.org" => {
@@ -310,7 +502,11 @@ pub fn get_pr_link(repo: &Repository) -> Option<String> {
             let path = provider_info.fullname(); // org/project/repo
 
             // Bitbucket format: https://bitbucket.org/OWNER/REPO/pull-requests/new?source=BRANCH
-            format!("https://bitbucket.org/{path}/pull-requests/new?source={branch_name}")
+            let dest = match &base {
+                Some(base) => format!("&dest={base}"),
+                None => String::new(),
+            };
+            format!("https://bitbucket.org/{path}/pull-requests/new?source={branch_name}{dest}")
         }
         _ => {
             let provider_info: GenericProvider = if let Ok(info) = parsed.provider_info() {
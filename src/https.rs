@@ -0,0 +1,266 @@
+use std::path::PathBuf;
+
+use git2::{Config, Cred, Error};
+
+use crate::helpers::home_dir;
+
+/// A token to try, and where it came from (for the error listing what was tried)
+struct Candidate {
+    source: String,
+    username: String,
+    token: String,
+}
+
+/// The host of an HTTPS URL, without user or port. Plain `http://` URLs give `None`, as
+/// anything sent over them can be read on the way.
+pub fn https_host(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("https://")?;
+    let authority = rest.split('/').next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    Some(host.to_lowercase())
+}
+
+/// The hosts environment tokens may be sent to. `GITHUB_TOKEN` and `GITLAB_TOKEN` go to
+/// github.com and gitlab.com, plus the hosts in `gg.githubHost`/`gg.gitlabHost` (e.g. GitHub
+/// Enterprise); `GG_TOKEN` only goes to the hosts in `gg.tokenHost`.
+#[derive(Debug, Default)]
+pub struct TokenHosts {
+    pub token: Vec<String>,
+    pub github: Vec<String>,
+    pub gitlab: Vec<String>,
+}
+
+impl TokenHosts {
+    /// Reads the host lists from global config only, so a repository can't redirect tokens
+    pub fn from_config() -> Self {
+        let Ok(config) = Config::open_default() else {
+            return Self::default();
+        };
+        let hosts = |key: &str| {
+            let mut hosts = Vec::new();
+            if let Ok(mut entries) = config.multivar(key, None) {
+                while let Some(Ok(entry)) = entries.next() {
+                    if let Some(host) = entry.value() {
+                        hosts.push(host.trim().to_lowercase());
+                    }
+                }
+            }
+            hosts
+        };
+        Self {
+            token: hosts("gg.tokenHost"),
+            github: hosts("gg.githubHost"),
+            gitlab: hosts("gg.gitlabHost"),
+        }
+    }
+
+    pub fn is_github(&self, host: &str) -> bool {
+        host == "github.com" || self.github.iter().any(|h| h == host)
+    }
+
+    pub fn is_gitlab(&self, host: &str) -> bool {
+        host == "gitlab.com" || self.gitlab.iter().any(|h| h == host)
+    }
+
+    pub fn takes_gg_token(&self, host: &str) -> bool {
+        self.token.iter().any(|h| h == host)
+    }
+}
+
+/// The username a forge expects alongside an access token
+pub fn token_username(host: &str, hosts: &TokenHosts) -> &'static str {
+    if hosts.is_github(host) {
+        "x-access-token"
+    } else if hosts.is_gitlab(host) {
+        "oauth2"
+    } else if host == "bitbucket.org" {
+        "x-token-auth"
+    } else {
+        "git"
+    }
+}
+
+/// Finds the login and password for `host` in the contents of a `.netrc` file,
+/// falling back to its `default` entry
+pub fn parse_netrc(contents: &str, host: &str) -> Option<(Option<String>, String)> {
+    let mut tokens = contents.split_whitespace();
+    // (is this entry for us, login, password)
+    let mut entries: Vec<(bool, Option<String>, Option<String>)> = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                let machine = tokens.next().unwrap_or_default();
+                entries.push((machine.eq_ignore_ascii_case(host), None, None));
+            }
+            "default" => entries.push((true, None, None)),
+            "login" | "password" => {
+                let value = tokens.next().map(str::to_string);
+                if let Some(entry) = entries.last_mut() {
+                    if token == "login" {
+                        entry.1 = value;
+                    } else {
+                        entry.2 = value;
+                    }
+                }
+            }
+            // Macros run until a blank line, which split_whitespace can't see; stop here
+            "macdef" => break,
+            _ => {}
+        }
+    }
+
+    // A `default` entry only applies when no machine matched, and is always last
+    entries
+        .into_iter()
+        .filter(|(applies, _, _)| *applies)
+        .find_map(|(_, login, password)| password.map(|p| (login, p)))
+}
+
+/// Finds the token for `host` in gg's credential file: one `<host> <token>` pair per line,
+/// or `<host> <username>:<token>` to override the forge's usual username
+pub fn parse_credentials_file(contents: &str, host: &str) -> Option<(Option<String>, String)> {
+    contents.lines().find_map(|line| {
+        let line = line.trim();
+        if line.starts_with('#') {
+            return None;
+        }
+        let (entry_host, secret) = line.split_once(char::is_whitespace)?;
+        if !entry_host.eq_ignore_ascii_case(host) {
+            return None;
+        }
+        let secret = secret.trim();
+        Some(match secret.split_once(':') {
+            Some((user, token)) => (Some(user.to_string()), token.to_string()),
+            None => (None, secret.to_string()),
+        })
+    })
+}
+
+/// The path of gg's credential file (`$XDG_CONFIG_HOME/gg/credentials`, or under `~/.config`)
+pub fn credentials_file_path() -> PathBuf {
+    std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| home_dir().join(".config"))
+        .join("gg")
+        .join("credentials")
+}
+
+/// Hands out HTTPS credentials one at a time, as libgit2 asks for them after each rejection:
+/// tokens from the environment, `~/.netrc` and gg's credential file, then git's credential helper.
+pub struct HttpsAuth {
+    host: Option<String>,
+    candidates: Vec<Candidate>,
+    helper_tried: bool,
+    tried: Vec<String>,
+}
+
+impl HttpsAuth {
+    pub fn new() -> Self {
+        Self {
+            host: None,
+            candidates: Vec::new(),
+            helper_tried: false,
+            tried: Vec::new(),
+        }
+    }
+
+    /// Gathers the tokens available for the URL's host the first time it is seen.
+    /// Plain `http://` URLs get none, and are left to git's credential helper.
+    fn load(&mut self, url: &str) {
+        let Some(host) = https_host(url) else {
+            self.candidates.clear();
+            self.host = Some(url.to_string());
+            return;
+        };
+        if self.host.as_deref() == Some(host.as_str()) {
+            return;
+        }
+
+        let hosts = TokenHosts::from_config();
+        let forge_user = token_username(&host, &hosts).to_string();
+        let mut candidates = Vec::new();
+        let mut env_token = |var: &str| {
+            if let Ok(token) = std::env::var(var)
+                && !token.is_empty()
+            {
+                candidates.push(Candidate {
+                    source: var.to_string(),
+                    username: forge_user.clone(),
+                    token,
+                });
+            }
+        };
+        if hosts.takes_gg_token(&host) {
+            env_token("GG_TOKEN");
+        }
+        if hosts.is_github(&host) {
+            env_token("GITHUB_TOKEN");
+        }
+        if hosts.is_gitlab(&host) {
+            env_token("GITLAB_TOKEN");
+        }
+
+        let netrc = std::fs::read_to_string(home_dir().join(".netrc")).unwrap_or_default();
+        if let Some((login, password)) = parse_netrc(&netrc, &host) {
+            candidates.push(Candidate {
+                source: "~/.netrc".to_string(),
+                username: login.unwrap_or_else(|| forge_user.clone()),
+                token: password,
+            });
+        }
+
+        let path = credentials_file_path();
+        let file = std::fs::read_to_string(&path).unwrap_or_default();
+        if let Some((user, token)) = parse_credentials_file(&file, &host) {
+            candidates.push(Candidate {
+                source: path.display().to_string(),
+                username: user.unwrap_or(forge_user),
+                token,
+            });
+        }
+
+        candidates.reverse();
+        self.candidates = candidates;
+        self.host = Some(host);
+    }
+
+    /// The next credential to try, or an error listing everything tried once none are left
+    pub fn next_credential(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+    ) -> Result<Cred, Error> {
+        self.load(url);
+
+        if let Some(candidate) = self.candidates.pop() {
+            self.tried.push(candidate.source);
+            return Cred::userpass_plaintext(&candidate.username, &candidate.token);
+        }
+
+        if !self.helper_tried {
+            self.helper_tried = true;
+            self.tried.push("git credential helper".to_string());
+            if let Ok(cred) = Config::open_default()
+                .and_then(|config| Cred::credential_helper(&config, url, username_from_url))
+            {
+                return Ok(cred);
+            }
+        }
+
+        Err(Error::from_str(&format!(
+            "HTTPS authentication failed for '{}'. Tried: {}. Set GG_TOKEN (and add the host to \
+             gg.tokenHost), add the host to ~/.netrc or {}, or configure a git credential helper.",
+            self.host.as_deref().unwrap_or_default(),
+            self.tried.join(", "),
+            credentials_file_path().display()
+        )))
+    }
+}
+
+impl Default for HttpsAuth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
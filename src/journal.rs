@@ -0,0 +1,390 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+
+use git2::{
+    Commit, Error, Index, IndexEntry, IndexTime, Oid, Repository, Signature, Status, StatusOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::committer_signature;
+use crate::link::create_backup_ref;
+
+/// How many operations the journal remembers
+const MAX_ENTRIES: usize = 100;
+/// Above this many bytes of changed files, the working directory is left out of a snapshot
+const MAX_WORKTREE_BYTES: u64 = 32 * 1024 * 1024;
+
+thread_local! {
+    /// Branches pushed by the current command, as "<remote>/<branch>"
+    static PUSHES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Records that the current command pushed a branch, so `gg undo` can warn about it
+pub fn note_push(remote: &str, branch: &str) {
+    PUSHES.with(|p| p.borrow_mut().push(format!("{remote}/{branch}")));
+}
+
+/// Takes the pushes recorded since the last call
+pub fn take_pushes() -> Vec<String> {
+    PUSHES.with(|p| std::mem::take(&mut *p.borrow_mut()))
+}
+
+/// The local state a gg command can change: HEAD, branches, index and working tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The branch HEAD points to ("refs/heads/<name>"), or the commit id when detached
+    pub head: String,
+    /// Branch name -> commit id
+    pub branches: BTreeMap<String, String>,
+    /// Tree of the index; `None` while it has conflicts
+    pub index: Option<String>,
+    /// Tree of the working directory, including untracked files; `None` while the index has
+    /// conflicts, or when the changed files are too large to copy on every command
+    pub worktree: Option<String>,
+}
+
+impl Snapshot {
+    pub fn take(repo: &Repository) -> Result<Self, Error> {
+        let head = match repo.find_reference("HEAD")?.symbolic_target() {
+            Some(target) => target.to_string(),
+            None => repo
+                .head()?
+                .target()
+                .map(|oid| oid.to_string())
+                .unwrap_or_default(),
+        };
+
+        let mut branches = BTreeMap::new();
+        for branch in repo.branches(Some(git2::BranchType::Local))? {
+            let (branch, _) = branch?;
+            if let (Some(name), Some(oid)) = (branch.name()?, branch.get().target()) {
+                branches.insert(name.to_string(), oid.to_string());
+            }
+        }
+
+        let index = repo.index()?.write_tree().ok();
+        let worktree = match index {
+            Some(tree) => worktree_tree(repo, tree)?.map(|oid| oid.to_string()),
+            None => None,
+        };
+
+        Ok(Self {
+            head,
+            branches,
+            index: index.map(|oid| oid.to_string()),
+            worktree,
+        })
+    }
+}
+
+/// Writes the working directory as a tree: the index tree plus every unstaged change.
+/// Returns `None` rather than copying more than `MAX_WORKTREE_BYTES` of changed files.
+fn worktree_tree(repo: &Repository, index_tree: Oid) -> Result<Option<Oid>, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::from_str("Repository has no workdir"))?;
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(true);
+    options.recurse_untracked_dirs(true);
+    let changed = Status::WT_NEW | Status::WT_MODIFIED | Status::WT_TYPECHANGE | Status::WT_DELETED;
+
+    // Size up the changes before writing any of them to the object database
+    let mut changes = Vec::new();
+    let mut total = 0;
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        let Some(path) = entry.path().map(str::to_string) else {
+            continue;
+        };
+        if !entry.status().intersects(changed) {
+            continue;
+        }
+        let metadata = fs::symlink_metadata(workdir.join(&path)).ok();
+        total += metadata.as_ref().map_or(0, fs::Metadata::len);
+        if total > MAX_WORKTREE_BYTES {
+            return Ok(None);
+        }
+        changes.push((path, metadata));
+    }
+
+    let mut index = Index::new()?;
+    index.read_tree(&repo.find_tree(index_tree)?)?;
+    for (path, metadata) in changes {
+        let full_path = workdir.join(&path);
+        let Some(metadata) = metadata else {
+            index.remove_path(path.as_ref())?;
+            continue;
+        };
+        let (id, mode) = if metadata.is_symlink() {
+            let target = fs::read_link(&full_path)
+                .map_err(|e| Error::from_str(&format!("Failed to read {path}: {e}")))?;
+            (repo.blob(target.to_string_lossy().as_bytes())?, 0o120000)
+        } else {
+            (repo.blob_path(&full_path)?, file_mode(&metadata))
+        };
+
+        index.add(&IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id,
+            flags: 0,
+            flags_extended: 0,
+            path: path.into_bytes(),
+        })?;
+    }
+    index.write_tree_to(repo).map(Some)
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    if metadata.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    0o100644
+}
+
+/// One gg command, with the state before and after it ran
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Unix time the command ran at
+    pub time: i64,
+    /// The command line, e.g. `save -m "Fix login"`
+    pub command: String,
+    pub before: Snapshot,
+    pub after: Snapshot,
+    /// Branches the command pushed, as "<remote>/<branch>"
+    #[serde(default)]
+    pub pushed: Vec<String>,
+    #[serde(default)]
+    pub undone: bool,
+    /// Where a forced undo saved the state it overwrote (see `backup_state`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+}
+
+impl JournalEntry {
+    /// The branches the command created, moved or deleted
+    pub fn ref_changes(&self) -> Vec<(String, Option<&str>, Option<&str>)> {
+        let names: BTreeSet<&String> = self
+            .before
+            .branches
+            .keys()
+            .chain(self.after.branches.keys())
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let old = self.before.branches.get(name).map(String::as_str);
+                let new = self.after.branches.get(name).map(String::as_str);
+                (old != new).then(|| (name.clone(), old, new))
+            })
+            .collect()
+    }
+}
+
+/// The journal file: one JSON entry per line, oldest first
+pub fn journal_path(repo: &Repository) -> PathBuf {
+    repo.path().join("gg").join("journal")
+}
+
+pub fn read_journal(repo: &Repository) -> Result<Vec<JournalEntry>, Error> {
+    let Ok(contents) = fs::read_to_string(journal_path(repo)) else {
+        return Ok(Vec::new());
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| Error::from_str(&format!("Corrupt journal entry: {e}")))
+        })
+        .collect()
+}
+
+fn write_journal(repo: &Repository, entries: &[JournalEntry]) -> Result<(), Error> {
+    let path = journal_path(repo);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| Error::from_str(&format!("Failed to create {}: {e}", parent.display())))?;
+    }
+
+    let start = entries.len().saturating_sub(MAX_ENTRIES);
+    let mut contents = String::new();
+    for entry in &entries[start..] {
+        let line = serde_json::to_string(entry)
+            .map_err(|e| Error::from_str(&format!("Failed to serialize journal: {e}")))?;
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    fs::write(&path, contents)
+        .map_err(|e| Error::from_str(&format!("Failed to write {}: {e}", path.display())))
+}
+
+/// Journals a command that ran between `before` and now, unless it changed nothing
+pub fn record(repo: &Repository, command: &str, before: Snapshot) -> Result<(), Error> {
+    let pushed = take_pushes();
+    let after = Snapshot::take(repo)?;
+    if before == after && pushed.is_empty() {
+        return Ok(());
+    }
+
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let mut entries = read_journal(repo)?;
+    entries.push(JournalEntry {
+        time,
+        command: command.to_string(),
+        before,
+        after,
+        pushed,
+        undone: false,
+        backup: None,
+    });
+    write_journal(repo, &entries)
+}
+
+/// Saves the state a forced undo is about to overwrite as a commit under `refs/gg/backup/`.
+/// Its tree holds the working directory, and its parents keep the current HEAD and every
+/// branch the undo moves or deletes reachable; the message lists where those branches were.
+fn backup_state(
+    repo: &Repository,
+    current: &Snapshot,
+    entry: &JournalEntry,
+) -> Result<String, Error> {
+    let mut parents: Vec<Commit> = repo
+        .head()
+        .and_then(|h| h.peel_to_commit())
+        .into_iter()
+        .collect();
+    let mut branches = Vec::new();
+    for (name, oid) in &current.branches {
+        if entry.before.branches.get(name) == Some(oid) {
+            continue;
+        }
+        branches.push(format!("  {name} {oid}"));
+        let commit = repo.find_commit(Oid::from_str(oid)?)?;
+        if !parents.iter().any(|p| p.id() == commit.id()) {
+            parents.push(commit);
+        }
+    }
+
+    let tree = match current.worktree.as_ref().or(current.index.as_ref()) {
+        Some(tree) => repo.find_tree(Oid::from_str(tree)?)?,
+        None => match parents.first() {
+            Some(head) => head.tree()?,
+            None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+        },
+    };
+
+    let mut message = format!("gg: state before undoing 'gg {}'\n", entry.command);
+    if !branches.is_empty() {
+        message.push_str(&format!("\nBranches:\n{}\n", branches.join("\n")));
+    }
+    let signature = committer_signature(repo).or_else(|_| Signature::now("gg", "gg@localhost"))?;
+    let parent_refs: Vec<&Commit> = parents.iter().collect();
+    let oid = repo.commit(None, &signature, &signature, &message, &tree, &parent_refs)?;
+    create_backup_ref(
+        repo,
+        oid,
+        &format!("gg undo: backup before undoing '{}'", entry.command),
+    )
+}
+
+/// Restores HEAD, branches, index and working directory to how they were before the last
+/// journaled command, and returns that command's entry. A command that pushed is only
+/// undone with `force`, as the push itself stays on the remote; so are local changes made
+/// since the command ran, which undoing would overwrite. Those changes are backed up first,
+/// and the returned entry says where.
+pub fn undo(repo: &Repository, force: bool) -> Result<JournalEntry, Error> {
+    let mut entries = read_journal(repo)?;
+    let Some(position) = entries.iter().rposition(|e| !e.undone) else {
+        return Err(Error::from_str("Nothing to undo."));
+    };
+    let mut entry = entries[position].clone();
+
+    if !force && !entry.pushed.is_empty() {
+        return Err(Error::from_str(&format!(
+            "'gg {}' pushed {}. Undoing it only changes your local repo, the remote keeps \
+             the pushed commits. Use 'gg undo --force' to undo it anyway.",
+            entry.command,
+            entry.pushed.join(", ")
+        )));
+    }
+    let current = Snapshot::take(repo)?;
+    if current != entry.after {
+        if !force {
+            return Err(Error::from_str(&format!(
+                "Your branches or files changed since 'gg {}' ran, and undoing it would \
+                 overwrite those changes. Use 'gg undo --force' to undo it anyway.",
+                entry.command
+            )));
+        }
+        entry.backup = Some(backup_state(repo, &current, &entry)?);
+    }
+
+    let before = &entry.before;
+    let reason = format!("gg undo: {}", entry.command);
+    for (name, oid) in &before.branches {
+        repo.reference(
+            &format!("refs/heads/{name}"),
+            Oid::from_str(oid)?,
+            true,
+            &reason,
+        )?;
+    }
+
+    if before.head.starts_with("refs/") {
+        repo.set_head(&before.head)?;
+    } else if !before.head.is_empty() {
+        repo.set_head_detached(Oid::from_str(&before.head)?)?;
+    }
+
+    for name in entry.after.branches.keys() {
+        if !before.branches.contains_key(name)
+            && let Ok(mut reference) = repo.find_reference(&format!("refs/heads/{name}"))
+        {
+            reference.delete()?;
+        }
+    }
+
+    if let Some(worktree) = &before.worktree {
+        let tree = repo.find_tree(Oid::from_str(worktree)?)?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        if current.worktree.is_some() {
+            checkout.force();
+        } else {
+            // The current files weren't backed up, so leave any that changed alone
+            checkout.safe().allow_conflicts(true);
+        }
+        repo.checkout_tree(tree.as_object(), Some(&mut checkout))?;
+    }
+    if let Some(index_tree) = &before.index {
+        let mut index = repo.index()?;
+        index.read_tree(&repo.find_tree(Oid::from_str(index_tree)?)?)?;
+        index.write()?;
+    }
+
+    entries[position] = JournalEntry {
+        undone: true,
+        ..entry.clone()
+    };
+    write_journal(repo, &entries)?;
+    Ok(entry)
+}
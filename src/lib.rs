@@ -1,2 +1,14 @@
 pub mod git_commands;
 pub mod helpers;
+pub mod https;
+pub mod journal;
+pub mod link;
+pub mod naming;
+pub mod profiles;
+pub mod progress;
+pub mod remotes;
+pub mod security;
+pub mod ssh;
+pub mod stack;
+pub mod stash;
+pub mod sync;
@@ -0,0 +1,360 @@
+use std::io::{self, IsTerminal, Write};
+
+use git2::{Error, Oid, Repository};
+use owo_colors::OwoColorize;
+
+use crate::git_commands::{fetch, resolve_conflicts_keeping_local};
+use crate::helpers::{commit_signature, committer_signature};
+
+/// How to join local work with a remote's history in `gg remote`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncStrategy {
+    /// Replay local commits on top of the remote branch (linear history)
+    Rebase,
+    /// Merge the two histories, like `git merge --allow-unrelated-histories`
+    Merge,
+}
+
+/// A commit shown in the sync preview
+#[derive(Debug)]
+pub struct CommitSummary {
+    pub id: Oid,
+    pub summary: String,
+}
+
+/// What joining the local branch with the remote one involves
+#[derive(Debug)]
+pub struct SyncPreview {
+    /// Commits only the local branch has, oldest first
+    pub local_only: Vec<CommitSummary>,
+    /// Commits only the remote branch has, oldest first
+    pub remote_only: Vec<CommitSummary>,
+    /// Files changed on both sides, which may conflict
+    pub overlapping: Vec<String>,
+    /// Whether the two histories share a commit at all
+    pub related: bool,
+}
+
+/// Lists commits reachable from `tip` but not from `hide`, oldest first
+fn commits_between(repo: &Repository, tip: Oid, hide: Oid) -> Result<Vec<CommitSummary>, Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    walk.push(tip)?;
+    walk.hide(hide)?;
+
+    let mut commits = Vec::new();
+    for id in walk {
+        let id = id?;
+        let commit = repo.find_commit(id)?;
+        commits.push(CommitSummary {
+            id,
+            summary: commit.summary().unwrap_or_default().to_string(),
+        });
+    }
+    Ok(commits)
+}
+
+/// Paths changed between two trees (`None` is the empty tree)
+fn changed_paths(
+    repo: &Repository,
+    from: Option<&git2::Tree>,
+    to: &git2::Tree,
+) -> Result<Vec<String>, Error> {
+    let diff = repo.diff_tree_to_tree(from, Some(to), None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|d| d.new_file().path().or(d.old_file().path()))
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Works out what syncing `local` with `remote` would do, without changing anything
+pub fn preview_sync(repo: &Repository, local: Oid, remote: Oid) -> Result<SyncPreview, Error> {
+    let base = repo.merge_base(local, remote).ok();
+    let base_tree = match base {
+        Some(base) => Some(repo.find_commit(base)?.tree()?),
+        None => None,
+    };
+
+    let local_files = changed_paths(repo, base_tree.as_ref(), &repo.find_commit(local)?.tree()?)?;
+    let remote_files = changed_paths(repo, base_tree.as_ref(), &repo.find_commit(remote)?.tree()?)?;
+    let mut overlapping: Vec<String> = local_files
+        .into_iter()
+        .filter(|f| remote_files.contains(f))
+        .collect();
+    overlapping.sort();
+
+    Ok(SyncPreview {
+        local_only: commits_between(repo, local, remote)?,
+        remote_only: commits_between(repo, remote, local)?,
+        overlapping,
+        related: base.is_some(),
+    })
+}
+
+fn print_commits(title: &str, commits: &[CommitSummary]) {
+    println!("{title} ({}):", commits.len());
+    for commit in commits.iter().take(10) {
+        let id = commit.id.to_string()[..7].to_string();
+        println!("  {} {}", id.yellow(), commit.summary);
+    }
+    if commits.len() > 10 {
+        println!("  ... (+{})", commits.len() - 10);
+    }
+}
+
+pub fn print_preview(preview: &SyncPreview, remote_ref: &str) {
+    println!("\n--- Sync preview with {remote_ref} ---");
+    if !preview.related {
+        println!("{}: The histories are unrelated.", "Note".yellow());
+    }
+    print_commits("Only local", &preview.local_only);
+    print_commits("Only on the remote", &preview.remote_only);
+    if preview.overlapping.is_empty() {
+        println!("No files changed on both sides.");
+    } else {
+        println!(
+            "Changed on both sides, may conflict ({}):",
+            preview.overlapping.len()
+        );
+        for path in &preview.overlapping {
+            println!("  - {path}");
+        }
+    }
+    println!();
+}
+
+/// Asks how to join the histories; without a terminal, rebasing is assumed
+fn ask_strategy() -> Option<SyncStrategy> {
+    if !io::stdin().is_terminal() {
+        return Some(SyncStrategy::Rebase);
+    }
+
+    print!("[r]ebase local commits onto the remote, [m]erge the histories, or [a]bort? [r]: ");
+    _ = io::stdout().flush();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).ok()?;
+    match input.trim().to_lowercase().as_str() {
+        "" | "r" | "rebase" => Some(SyncStrategy::Rebase),
+        "m" | "merge" => Some(SyncStrategy::Merge),
+        _ => None,
+    }
+}
+
+/// What a rebase rewrote, so it can be checked and undone
+#[derive(Debug)]
+pub struct RebaseAudit {
+    /// Ref keeping the pre-rebase tip reachable (`refs/gg/backup/<timestamp>`)
+    pub backup_ref: String,
+    /// Each replayed commit, as (old id, new id)
+    pub rewritten: Vec<(Oid, Oid)>,
+    /// Whether conflicts were resolved with the local version along the way
+    pub conflicted: bool,
+}
+
+/// Points a new `refs/gg/backup/<timestamp>` ref at `target`
+pub fn create_backup_ref(repo: &Repository, target: Oid, reason: &str) -> Result<String, Error> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    // Several backups within one second get a suffix
+    let mut name = format!("refs/gg/backup/{timestamp}");
+    let mut n = 1;
+    while repo.find_reference(&name).is_ok() {
+        name = format!("refs/gg/backup/{timestamp}-{n}");
+        n += 1;
+    }
+    repo.reference(&name, target, false, reason)?;
+    Ok(name)
+}
+
+/// Replays the local commits onto `onto`, keeping each commit's author and message.
+/// The old tip is saved under a backup ref first. Conflicts are resolved with the local
+/// version, keeping the remote one as a `.theirs` file.
+fn rebase_onto(repo: &Repository, onto: &git2::AnnotatedCommit) -> Result<RebaseAudit, Error> {
+    let old_tip = repo.head()?.peel_to_commit()?.id();
+    let backup_ref = create_backup_ref(repo, old_tip, "gg: backup before sync rebase")?;
+
+    let committer = committer_signature(repo)?;
+    let mut rebase = repo.rebase(None, Some(onto), None, None)?;
+    let mut audit = RebaseAudit {
+        backup_ref,
+        rewritten: Vec::new(),
+        conflicted: false,
+    };
+
+    let replayed = replay_commits(repo, &mut rebase, &committer, &mut audit)
+        .and_then(|()| rebase.finish(Some(&committer)));
+    if let Err(e) = replayed {
+        // Don't leave the repo mid-rebase with HEAD detached
+        if let Err(abort) = rebase.abort() {
+            return Err(Error::from_str(&format!(
+                "{} The rebase could not be undone either ({}); run 'git rebase --abort'.",
+                e.message(),
+                abort.message()
+            )));
+        }
+        return Err(e);
+    }
+    Ok(audit)
+}
+
+/// Commits each step of `rebase`, recording the rewritten commits in `audit`
+fn replay_commits(
+    repo: &Repository,
+    rebase: &mut git2::Rebase,
+    committer: &git2::Signature,
+    audit: &mut RebaseAudit,
+) -> Result<(), Error> {
+    while let Some(op) = rebase.next() {
+        let original = repo.find_commit(op?.id())?;
+        let mut index = repo.index()?;
+        if index.has_conflicts() {
+            // While rebasing, the commit being replayed (local work) is "theirs"
+            resolve_conflicts_keeping_local(repo, &mut index, false)?;
+            index.write()?;
+            audit.conflicted = true;
+        }
+        match rebase.commit(Some(&original.author()), committer, original.message()) {
+            Ok(new_id) => audit.rewritten.push((original.id(), new_id)),
+            // The change is already on the remote, nothing to replay
+            Err(e) if e.code() == git2::ErrorCode::Applied => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn print_audit(repo: &Repository, audit: &RebaseAudit) {
+    println!("--- Rewritten commits ({}) ---", audit.rewritten.len());
+    for (old, new) in &audit.rewritten {
+        let summary = repo
+            .find_commit(*new)
+            .ok()
+            .and_then(|c| c.summary().map(str::to_string))
+            .unwrap_or_default();
+        println!(
+            "  {} -> {}  {summary}",
+            old.to_string()[..7].to_string().dimmed(),
+            new.to_string()[..7].to_string().yellow()
+        );
+    }
+    println!(
+        "Your previous work is kept at '{0}'. To go back: git reset --hard {0}",
+        audit.backup_ref
+    );
+}
+
+/// Merges `theirs` into HEAD, even when the histories are unrelated.
+/// Conflicts are resolved like in `gg pull`. Returns whether any conflict happened.
+fn merge_histories(repo: &Repository, theirs: Oid, remote_ref: &str) -> Result<bool, Error> {
+    let ours = repo.head()?.peel_to_commit()?;
+    let theirs = repo.find_commit(theirs)?;
+
+    let base_tree = match repo.merge_base(ours.id(), theirs.id()) {
+        Ok(base) => repo.find_commit(base)?.tree()?,
+        // Unrelated histories: merge as if both sides started from nothing
+        Err(_) => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+
+    let mut index = repo.merge_trees(&base_tree, &ours.tree()?, &theirs.tree()?, None)?;
+    let conflicted = index.has_conflicts();
+    if conflicted {
+        resolve_conflicts_keeping_local(repo, &mut index, true)?;
+    }
+
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    let author = commit_signature(repo)?;
+    let committer = committer_signature(repo)?;
+    let head = repo.head()?.shorthand().unwrap_or("HEAD").to_string();
+    repo.commit(
+        Some("HEAD"),
+        &author,
+        &committer,
+        &format!("Merge remote-tracking branch '{remote_ref}' into {head}"),
+        &tree,
+        &[&ours, &theirs],
+    )?;
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
+    Ok(conflicted)
+}
+
+/// Joins the current branch with its namesake on `remote_name`, whose history may be unrelated
+/// (e.g. a repo created with a README on the forge). Shows a preview first, then rebases or
+/// merges according to `strategy`, asking when it isn't given.
+pub fn sync_unrelated_histories(
+    repo: &Repository,
+    remote_name: &str,
+    strategy: Option<SyncStrategy>,
+) -> Result<(), Error> {
+    // Fetch to see what the remote has
+    fetch(repo, remote_name, false)?;
+
+    // Determine local branch name (usually 'main' or 'master')
+    let local_branch_name = repo
+        .head()
+        .map(|h| h.shorthand().unwrap_or("main").to_string())
+        .unwrap_or_else(|_| "main".to_string());
+
+    let remote_ref_name = format!("refs/remotes/{remote_name}/{local_branch_name}");
+    let remote_short = format!("{remote_name}/{local_branch_name}");
+
+    let Ok(remote_ref) = repo.find_reference(&remote_ref_name) else {
+        println!("--- Remote is empty. Ready for your first 'Save'. ---");
+        return Ok(());
+    };
+    let remote_commit = repo.reference_to_annotated_commit(&remote_ref)?;
+
+    match repo.head() {
+        Ok(head) => {
+            let local = head.peel_to_commit()?.id();
+            if local != remote_commit.id() {
+                let preview = preview_sync(repo, local, remote_commit.id())?;
+                print_preview(&preview, &remote_short);
+
+                let conflicted = if preview.remote_only.is_empty() {
+                    // Nothing to bring in: the local branch is already ahead
+                    false
+                } else {
+                    match strategy.or_else(ask_strategy) {
+                        Some(SyncStrategy::Rebase) => {
+                            println!("--- Rebasing local work onto {remote_short} ---");
+                            let audit = rebase_onto(repo, &remote_commit)?;
+                            print_audit(repo, &audit);
+                            audit.conflicted
+                        }
+                        Some(SyncStrategy::Merge) => {
+                            println!("--- Merging {remote_short} into {local_branch_name} ---");
+                            merge_histories(repo, remote_commit.id(), &remote_short)?
+                        }
+                        None => return Err(Error::from_str("Sync aborted, nothing was changed.")),
+                    }
+                };
+
+                if conflicted {
+                    println!(
+                        "\n{}: Conflicts were resolved with your version. Review the '.theirs' \
+                         files with 'gg resolve', then 'gg resolve --cleanup'.",
+                        "Note".yellow()
+                    );
+                }
+            }
+        }
+        Err(_) => {
+            println!("--- Initializing local branch from remote ---");
+            let remote_commit = repo.find_commit(remote_commit.id())?;
+            repo.branch(&local_branch_name, &remote_commit, false)?;
+            repo.set_head(&format!("refs/heads/{local_branch_name}"))?;
+            repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
+        }
+    }
+
+    // Link the branches for future 'Save' calls
+    let mut branch = repo.find_branch(&local_branch_name, git2::BranchType::Local)?;
+    branch.set_upstream(Some(&remote_short))?;
+    println!("--- Tracking relationship established ---");
+
+    Ok(())
+}
@@ -1,12 +1,31 @@
 mod git_commands;
 mod helpers;
+mod https;
+mod journal;
+mod link;
+mod naming;
+mod profiles;
+mod progress;
+mod remotes;
+mod security;
+mod ssh;
+mod stack;
+mod stash;
+mod sync;
 
 use clap::{Parser, Subcommand};
 use git2::{Error, Repository};
 
 use git_commands::*;
 use helpers::*;
+use link::SyncStrategy;
+use naming::*;
 use owo_colors::OwoColorize;
+use profiles::*;
+use remotes::*;
+use stack::*;
+use stash::*;
+use sync::*;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -19,6 +38,10 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
+    /// Stash local changes (including untracked files) before running, and restore them after
+    #[arg(long, global = true, default_value_t = false)]
+    autostash: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -26,17 +49,57 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Push {},
-    Pull {},
+    /// Update remote-tracking branches
+    Fetch {
+        /// Fetch every remote, not just origin
+        #[arg(long, default_value_t = false)]
+        all: bool,
+
+        /// Remove tracking branches that no longer exist on the remote
+        #[arg(long, default_value_t = false)]
+        prune: bool,
+    },
+    /// Pull the current branch's upstream into it
+    Pull {
+        /// Merge this remote branch instead of the upstream
+        #[arg(long)]
+        from: Option<String>,
+    },
     /// Git pull + switch [-c] <name> [+ push]
     Feature {
+        /// Branch name or free-text description (e.g. "Add login page")
         name: String,
 
         #[arg(short, long)]
         base: Option<String>,
+
+        /// Kind of branch, used as the name prefix
+        #[arg(short = 't', long = "type", value_enum, default_value_t = BranchKind::Feature)]
+        kind: BranchKind,
+
+        /// Ticket ID to insert into the branch name (e.g. ABC-123)
+        #[arg(long)]
+        ticket: Option<String>,
+
+        /// Use the name exactly as typed, skipping the naming template
+        #[arg(long, default_value_t = false)]
+        raw: bool,
+
+        /// Branch without pulling first, and queue the push for 'gg sync'
+        #[arg(long, default_value_t = false)]
+        offline: bool,
     },
 
-    /// List all branches
-    Features {},
+    /// Show a dashboard of all local branches
+    Features {
+        /// How to order the branches
+        #[arg(short, long, value_enum, default_value_t = BranchSort::Name)]
+        sort: BranchSort,
+
+        /// Print the dashboard as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
 
     /// Git pull + commit + push
     Save {
@@ -45,30 +108,77 @@ enum Commands {
 
         #[arg(long, group = "type", default_value_t = false)]
         amend: bool,
+
+        /// Take back the commit of a save whose push failed, leaving its changes staged
+        #[arg(long, group = "type", default_value_t = false)]
+        rollback: bool,
+
+        /// Commit without pulling first, and queue the push for 'gg sync'
+        #[arg(long, default_value_t = false, conflicts_with = "rollback")]
+        offline: bool,
     },
 
+    /// Fetch once, then fast-forward, push or report every branch that has an upstream
+    Sync {},
+
     /// Git switch main + git pull [+ git branch -D <branch>]
     Done {
         #[arg(short, long, default_value_t = false)]
         no_clean: bool,
     },
 
+    /// Set the commit identity, or manage named identity profiles
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Creds {
-        name: String,
-        email: String,
+        #[command(subcommand)]
+        action: Option<CredsCommands>,
+
+        #[arg(required_unless_present = "show")]
+        name: Option<String>,
+        #[arg(required_unless_present = "show")]
+        email: Option<String>,
 
         /// Set settings globally (~/.gitconfig) instead of locally
         #[arg(short, long)]
         global: bool,
+
+        /// Show the current identity and the config scope each value comes from
+        #[arg(long, default_value_t = false, conflicts_with_all = ["name", "email", "global"])]
+        show: bool,
     },
-    /// Set or update a remote URL (defaults to origin)
+    /// Set or update a remote URL (defaults to origin), or manage remotes
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Remote {
+        #[command(subcommand)]
+        action: Option<RemoteCommands>,
+
         /// The URL of the remote (e.g., git@github.com:user/repo.git)
-        url: String,
+        #[arg(required = true)]
+        url: Option<String>,
 
         /// The name of the remote
         #[arg(short, long, default_value = "origin")]
         name: String,
+
+        /// Only set the URL, without syncing histories and pushing
+        #[arg(long, default_value_t = false)]
+        no_sync: bool,
+
+        /// How to join local work with the remote's history (asks when not given)
+        #[arg(long, value_enum, conflicts_with = "no_sync")]
+        strategy: Option<SyncStrategy>,
+    },
+    /// Rebase stacked branches onto their updated (or merged) parents
+    Restack {},
+
+    /// Stash local changes under a name, or manage existing stashes
+    #[command(args_conflicts_with_subcommands = true)]
+    Stash {
+        #[command(subcommand)]
+        action: Option<StashCommands>,
+
+        /// Name to store the stash under
+        name: Option<String>,
     },
     /// Find and manage conflicts create by pulls
     Resolve {
@@ -76,6 +186,12 @@ enum Commands {
         #[arg(long, default_value_t = false)]
         cleanup: bool,
     },
+    /// Put HEAD, branches, the index and files back as they were before the last gg command
+    Undo {
+        /// Also undo a command that pushed, or one followed by other changes
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
     /// Generate the URL for a pull request
     PR {
         #[arg(short, long, default_value_t = false)]
@@ -83,6 +199,105 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum StashCommands {
+    /// List stashes with their branch, age and files
+    List {
+        /// Print the stashes as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Apply a stash, keeping it in the stash list
+    Apply {
+        /// Stash name, stash@{n}, or part of its message
+        name: String,
+    },
+    /// Apply a stash and drop it if it applied cleanly
+    Pop {
+        /// Stash name, stash@{n}, or part of its message
+        name: String,
+    },
+    /// Drop a stash (the latest one if no name is given)
+    Drop {
+        /// Stash name, stash@{n}, or part of its message
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CredsCommands {
+    /// Create or replace a named identity profile (stored in ~/.gitconfig)
+    Add {
+        /// Name of the profile, e.g. "work"
+        profile: String,
+        name: String,
+        email: String,
+
+        /// Use the profile for remotes on this host (glob, repeatable)
+        #[arg(long)]
+        host: Vec<String>,
+
+        /// Use the profile for remotes of this owner/organization (glob, repeatable)
+        #[arg(long)]
+        owner: Vec<String>,
+
+        /// Use the profile for repos under this path (glob, trailing '/' for subfolders, repeatable)
+        #[arg(long)]
+        path: Vec<String>,
+    },
+    /// Set the identity of a profile, for this repo or globally
+    Use {
+        profile: String,
+
+        /// Set settings globally (~/.gitconfig) instead of locally
+        #[arg(short, long)]
+        global: bool,
+    },
+    /// List identity profiles and their rules
+    List {},
+    /// Delete an identity profile
+    Remove { profile: String },
+}
+
+#[derive(Subcommand)]
+enum RemoteCommands {
+    /// Add a remote (or update its URL), then sync with it
+    Add {
+        /// The URL of the remote (e.g., git@github.com:user/repo.git)
+        url: String,
+
+        /// The name of the remote
+        #[arg(short, long, default_value = "origin")]
+        name: String,
+
+        /// Only set the URL, without syncing histories and pushing
+        #[arg(long, default_value_t = false)]
+        no_sync: bool,
+
+        /// How to join local work with the remote's history (asks when not given)
+        #[arg(long, value_enum, conflicts_with = "no_sync")]
+        strategy: Option<SyncStrategy>,
+    },
+    /// List remotes with their fetch/push URLs and forge
+    List {
+        /// Print the remotes as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Rename a remote, along with its tracking branches
+    Rename { old: String, new: String },
+    /// Remove a remote, along with its tracking branches
+    Remove { name: String },
+    /// Push to a different URL than the one fetched from
+    SetPushUrl {
+        url: String,
+
+        /// The name of the remote
+        #[arg(short, long, default_value = "origin")]
+        name: String,
+    },
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -94,64 +309,247 @@ fn main() {
 
 fn run(cli: Cli) -> Result<(), Error> {
     let path_str = cli.path;
-    let repo = Repository::open(&path_str)?;
+    let autostash = cli.autostash;
+    let mut repo = Repository::open(&path_str)?;
 
     match cli.command {
         Commands::Feature { .. }
         | Commands::Features { .. }
+        | Commands::Fetch { .. }
         | Commands::Save { .. }
         | Commands::Creds { .. }
-        | Commands::Resolve { .. } => {
+        | Commands::Stash { .. }
+        | Commands::Resolve { .. }
+        | Commands::Undo { .. }
+        | Commands::Sync { .. }
+        | Commands::Remote { no_sync: true, .. }
+        | Commands::Remote {
+            action:
+                Some(
+                    RemoteCommands::Add { no_sync: true, .. }
+                    | RemoteCommands::List { .. }
+                    | RemoteCommands::Rename { .. }
+                    | RemoteCommands::Remove { .. }
+                    | RemoteCommands::SetPushUrl { .. },
+                ),
+            ..
+        } => {
             // These commands are allowed to run in a dirty repo
         }
         _ => {
-            // All other commands (Feature, Done, Remote) require a clean state
-            if is_dirty(&repo)? {
+            // All other commands (Push, Pull, Done, Remote) require a clean state,
+            // unless the changes are stashed away for the duration of the command
+            if !autostash && is_dirty(&repo)? {
                 eprintln!("Error: You have unstaged changes or untracked files.");
-                eprintln!("Please 'Save' your work or stash your changes before proceeding.");
+                eprintln!(
+                    "Please 'Save' your work, stash your changes or use --autostash before proceeding."
+                );
                 std::process::exit(1);
             }
         }
     };
 
-    match cli.command {
+    // Journal what each command changes, so 'gg undo' can put it back. Commands that can't
+    // touch branches, the index or files skip the snapshot, which copies changed files.
+    let journaled = !matches!(
+        cli.command,
+        Commands::Fetch { .. }
+            | Commands::Features { .. }
+            | Commands::Creds { .. }
+            | Commands::PR { .. }
+            | Commands::Undo { .. }
+            | Commands::Stash {
+                action: Some(StashCommands::List { .. }),
+                ..
+            }
+            | Commands::Remote { no_sync: true, .. }
+            | Commands::Remote {
+                action: Some(
+                    RemoteCommands::Add { no_sync: true, .. }
+                        | RemoteCommands::List { .. }
+                        | RemoteCommands::Rename { .. }
+                        | RemoteCommands::Remove { .. }
+                        | RemoteCommands::SetPushUrl { .. }
+                ),
+                ..
+            }
+    );
+    let before = if journaled {
+        journal::Snapshot::take(&repo).ok()
+    } else {
+        None
+    };
+
+    let result = execute(cli.command, &mut repo, autostash);
+
+    if let Some(before) = before {
+        let command = std::env::args()
+            .skip(1)
+            .map(|arg| {
+                if arg.contains(char::is_whitespace) {
+                    format!("\"{arg}\"")
+                } else {
+                    arg
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Err(e) = journal::record(&repo, &command, before) {
+            eprintln!("Warning: Could not journal this command: {}", e.message());
+        }
+    }
+    result
+}
+
+fn execute(command: Commands, repo: &mut Repository, autostash: bool) -> Result<(), Error> {
+    match command {
         Commands::Push {} => {
             show_progress("Pushing", || {
                 let head = repo.head()?;
                 let branch_name = head.shorthand().unwrap_or("HEAD");
-                push(&repo, "origin", branch_name, false)
+                push(repo, "origin", branch_name, false)
             })?;
         }
-        Commands::Pull {} => {
-            show_progress("Pulling", || pull(&repo, "origin", "HEAD"))?;
+        Commands::Fetch { all, prune } => {
+            if all {
+                fetch_all(repo, prune)?;
+            } else {
+                show_progress("Fetching 'origin'", || fetch(repo, "origin", prune))?;
+            }
+        }
+        Commands::Pull { from } => {
+            autostash_if(autostash, repo, "pull", |repo| {
+                show_progress("Pulling", || pull(repo, "origin", from.as_deref()))
+            })?;
         }
-        Commands::Features {} => {
-            let branches = repo.branches(Some(git2::BranchType::Local))?;
+        Commands::Features { sort, json } => {
+            let branches = branch_dashboard(repo, sort)?;
+
+            if json {
+                let out = serde_json::to_string_pretty(&branches)
+                    .map_err(|e| Error::from_str(&format!("Failed to serialize branches: {e}")))?;
+                println!("{out}");
+                return Ok(());
+            }
+
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            let name_width = branches.iter().map(|b| b.name.len()).max().unwrap_or(0);
+            let upstream_width = branches
+                .iter()
+                .map(|b| b.upstream.as_deref().unwrap_or("-").len())
+                .max()
+                .unwrap_or(0);
+
             for b in branches {
-                let (branch, _) = b?;
-                println!("{}", branch.name()?.unwrap_or("HEAD"));
+                let marker = if b.is_head { "*" } else { " " };
+                let name = format!("{:name_width$}", b.name);
+                let name = if b.is_head {
+                    name.green().bold().to_string()
+                } else {
+                    name
+                };
+                let upstream = b.upstream.as_deref().unwrap_or("-");
+                let sync = format!("↑{} ↓{}", b.ahead, b.behind);
+                let age = format_age(now - b.last_commit_time);
+
+                let mut flags = Vec::new();
+                if b.merged {
+                    flags.push("merged".cyan().to_string());
+                }
+                if b.pending_conflicts {
+                    flags.push("conflicts".red().to_string());
+                }
+
+                println!(
+                    "{marker} {name}  {upstream:upstream_width$}  {sync:8}  {age:16}  {}  {}",
+                    b.last_commit_author.dimmed(),
+                    flags.join(" ")
+                );
             }
         }
-        Commands::Feature { name, base } => {
-            create_feature_branch(&repo, &name, base)?;
+        Commands::Feature {
+            name,
+            base,
+            kind,
+            ticket,
+            raw,
+            offline,
+        } => {
+            let name = resolve_branch_name(repo, &name, kind, ticket.as_deref(), raw)?;
+            // Local changes travel with us onto the new branch
+            with_autostash(repo, &format!("feature {name}"), |repo| {
+                create_feature_branch(repo, &name, base, offline)
+            })?;
+        }
+        Commands::Save { rollback: true, .. } => {
+            let commit = rollback_commit(repo)?;
+            println!(
+                "--- Rolled back '{}' ({}), its changes are staged ---",
+                commit.summary().unwrap_or_default(),
+                &commit.id().to_string()[..7]
+            );
         }
-        Commands::Save { message, amend } => {
-            show_progress("Pulling", || pull(&repo, "origin", "HEAD"))?;
+        Commands::Save {
+            message,
+            amend,
+            offline,
+            ..
+        } => {
+            match check_identity(repo)? {
+                IdentityCheck::Fine => {}
+                IdentityCheck::Applied(profile) => println!(
+                    "--- Using profile '{}' for this repo: {} <{}> ---",
+                    profile.profile.bold(),
+                    profile.name,
+                    profile.email
+                ),
+                IdentityCheck::Mismatch {
+                    profile,
+                    name,
+                    email,
+                } => eprintln!(
+                    "{}: Committing as {name} <{email}>, but profile '{}' ({} <{}>) matches this repo. \
+                     Run 'gg creds use {}' to switch.",
+                    "Warning".yellow(),
+                    profile.profile,
+                    profile.name,
+                    profile.email,
+                    profile.profile
+                ),
+            }
 
-            let msg = show_progress("Staging and Analyzing", || {
-                let mut index = repo.index()?;
-                index.add_all(["."].iter(), git2::IndexAddOption::DEFAULT, None)?;
-                index.write()?;
+            let missing = missing_identity(repo)?;
+            if !missing.is_empty() && !prompt_identity(repo, &missing)? {
+                return Err(Error::from_str(&format!(
+                    "Cannot save: {} not set. Run 'gg creds \"Your Name\" \"you@example.com\"' first.",
+                    missing.join(" and ")
+                )));
+            }
 
-                match message {
-                    Some(m) => Ok(m),
-                    None => generate_conventional_message(&repo),
+            progress::take_finished_steps();
+            let saved = (|| -> Result<(), Error> {
+                if !offline {
+                    autostash_if(autostash, repo, "save", |repo| {
+                        show_progress("Pulling", || pull(repo, "origin", None))
+                    })?;
                 }
-            })?;
 
-            show_progress("Committing", || commit_all(&repo, &msg, amend))?;
+                let msg = show_progress("Staging and Analyzing", || {
+                    let mut index = repo.index()?;
+                    index.add_all(["."].iter(), git2::IndexAddOption::DEFAULT, None)?;
+                    index.write()?;
+
+                    match message {
+                        Some(m) => Ok(m),
+                        None => generate_conventional_message(repo),
+                    }
+                })?;
+
+                show_progress("Committing", || commit_all(repo, &msg, amend))?;
 
-            show_progress("Pushing", || {
                 let head = repo.head()?;
 
                 // Ensure we are actually on a branch
@@ -162,50 +560,282 @@ fn run(cli: Cli) -> Result<(), Error> {
                         "You are in a detached HEAD state. Run 'git checkout main' first.",
                     ));
                 }
-
                 let branch_name = head.shorthand().unwrap_or("main");
-                push(&repo, "origin", branch_name, amend)
-            })?;
+
+                if offline {
+                    queue_push(repo, branch_name, amend)?;
+                    println!(
+                        "--- Committed offline. 'gg sync' will push '{}' once you're back online ---",
+                        branch_name.bold()
+                    );
+                    return Ok(());
+                }
+                show_progress("Pushing", || push(repo, "origin", branch_name, amend))
+            })();
+            if let Err(e) = saved {
+                return Err(save_failure(e, &progress::take_finished_steps()));
+            }
+        }
+        Commands::Sync {} => {
+            sync_branches(repo)?;
         }
         Commands::Done { no_clean } => {
-            done(&repo, no_clean, true)?;
+            autostash_if(autostash, repo, "done", |repo| done(repo, no_clean, true))?;
         }
         Commands::Creds {
+            action,
             name,
             email,
             global,
-        } => {
-            configure_git(&repo, &name, &email, global)?;
-            let scope = if global { "globally" } else { "locally" };
-            println!("--- Configured {scope} as {name} <{email}> ---");
-        }
-        Commands::Remote { url, name } => {
-            // 1. Set or Update URL
-            match repo.find_remote(&name) {
-                Ok(_) => repo.remote_set_url(&name, &url)?,
-                Err(_) => {
-                    repo.remote(&name, &url)?;
+            show,
+        } => match action {
+            None if show => {
+                for value in identity_sources(repo)? {
+                    match (value.value, value.scope) {
+                        (Some(v), Some(scope)) => {
+                            println!("{:<11} {v}  ({scope})", value.key.bold())
+                        }
+                        _ => println!("{:<11} {}", value.key.bold(), "(not set)".red()),
+                    }
+                }
+                if let Some(profile) = matching_profile(repo)? {
+                    println!("{:<11} {}", "profile".bold(), profile.profile);
                 }
             }
-            println!("--- Remote '{name}' set to {url} ---");
+            None => {
+                let (name, email) = (name.unwrap_or_default(), email.unwrap_or_default());
+                configure_git(repo, &name, &email, global)?;
+                let scope = if global { "globally" } else { "locally" };
+                println!("--- Configured {scope} as {name} <{email}> ---");
+            }
+            Some(CredsCommands::Add {
+                profile,
+                name,
+                email,
+                host,
+                owner,
+                path,
+            }) => {
+                let profile = Profile {
+                    profile,
+                    name,
+                    email,
+                    hosts: host,
+                    owners: owner,
+                    paths: path,
+                };
+                add_profile(&mut git2::Config::open_default()?, &profile)?;
+                println!(
+                    "--- Saved profile '{}': {} <{}> ---",
+                    profile.profile.bold(),
+                    profile.name,
+                    profile.email
+                );
+            }
+            Some(CredsCommands::Use { profile, global }) => {
+                let profile = find_profile(&repo.config()?, &profile)?;
+                configure_git(repo, &profile.name, &profile.email, global)?;
+                let scope = if global { "globally" } else { "locally" };
+                println!(
+                    "--- Using profile '{}' {scope}: {} <{}> ---",
+                    profile.profile.bold(),
+                    profile.name,
+                    profile.email
+                );
+            }
+            Some(CredsCommands::List {}) => {
+                let profiles = list_profiles(&repo.config()?)?;
+                if profiles.is_empty() {
+                    println!("No identity profiles. Create one with 'gg creds add'.");
+                }
+                let active = matching_profile(repo)?.map(|p| p.profile);
+                for profile in profiles {
+                    let marker = if active.as_ref() == Some(&profile.profile) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!(
+                        "{marker} {}  {} <{}>",
+                        profile.profile.bold(),
+                        profile.name,
+                        profile.email
+                    );
+                    for (kind, rules) in [
+                        ("host", &profile.hosts),
+                        ("owner", &profile.owners),
+                        ("path", &profile.paths),
+                    ] {
+                        for rule in rules {
+                            println!("      {kind}: {rule}");
+                        }
+                    }
+                }
+            }
+            Some(CredsCommands::Remove { profile }) => {
+                remove_profile(&mut git2::Config::open_default()?, &profile)?;
+                println!("--- Removed profile '{profile}' ---");
+            }
+        },
+        Commands::Remote {
+            action,
+            url,
+            name,
+            no_sync,
+            strategy,
+        } => match action {
+            None => {
+                let url = url.unwrap_or_default();
+                autostash_if(autostash, repo, "remote", |repo| {
+                    add_remote(repo, &name, &url, !no_sync, strategy)
+                })?;
+            }
+            Some(RemoteCommands::Add {
+                url,
+                name,
+                no_sync,
+                strategy,
+            }) => {
+                autostash_if(autostash, repo, "remote", |repo| {
+                    add_remote(repo, &name, &url, !no_sync, strategy)
+                })?;
+            }
+            Some(RemoteCommands::List { json }) => {
+                let remotes = list_remotes(repo)?;
+                if json {
+                    let out = serde_json::to_string_pretty(&remotes).map_err(|e| {
+                        Error::from_str(&format!("Failed to serialize remotes: {e}"))
+                    })?;
+                    println!("{out}");
+                    return Ok(());
+                }
 
-            // 2. Perform the "weird shit" sync automatically
-            println!("--- Syncing with remote ---");
-            if let Err(e) = sync_unrelated_histories(&repo, &name) {
-                eprintln!("--- Sync Note: {e} ---");
-                // We don't exit(1) here because the remote URL is still set successfully
-            } else {
-                println!("--- Pushing ---");
-                let head = repo.head()?;
-                let branch_name = head.shorthand().unwrap_or("HEAD");
-                push(&repo, "origin", branch_name, false)?;
+                if remotes.is_empty() {
+                    println!("No remotes. Add one with 'gg remote <url>'.");
+                }
+                let width = remotes.iter().map(|r| r.name.len()).max().unwrap_or(0);
+                for remote in remotes {
+                    let fetch_url = remote.fetch_url.unwrap_or_default();
+                    println!(
+                        "{:<width$}  {}  {fetch_url}",
+                        remote.name.bold(),
+                        format!("[{}]", remote.forge.name()).dimmed()
+                    );
+                    if let Some(push_url) = remote.push_url.filter(|p| *p != fetch_url) {
+                        println!("{:<width$}  push: {push_url}", "");
+                    }
+                }
+            }
+            Some(RemoteCommands::Rename { old, new }) => {
+                let problems = rename_remote(repo, &old, &new)?;
+                println!("--- Renamed remote '{old}' to '{new}' ---");
+                for refspec in problems {
+                    println!(
+                        "{}: Could not rename the refspec '{refspec}', update it by hand.",
+                        "Note".yellow()
+                    );
+                }
+            }
+            Some(RemoteCommands::Remove { name }) => {
+                remove_remote(repo, &name)?;
+                println!("--- Removed remote '{name}' ---");
             }
+            Some(RemoteCommands::SetPushUrl { url, name }) => {
+                set_push_url(repo, &name, &url)?;
+                println!("--- Pushes to '{name}' now go to {url} ---");
+            }
+        },
+        Commands::Restack {} => {
+            autostash_if(autostash, repo, "restack", restack)?;
         }
+        Commands::Stash { action, name } => match action {
+            None => {
+                match show_progress("Stashing local changes", || {
+                    stash_named(repo, name.as_deref())
+                })? {
+                    Some(_) => println!(
+                        "--- Saved as '{}' ---",
+                        name.as_deref().unwrap_or("wip").bold()
+                    ),
+                    None => println!("No local changes to stash."),
+                }
+            }
+            Some(StashCommands::List { json }) => {
+                let stashes = list_stashes(repo)?;
+
+                if json {
+                    let out = serde_json::to_string_pretty(&stashes).map_err(|e| {
+                        Error::from_str(&format!("Failed to serialize stashes: {e}"))
+                    })?;
+                    println!("{out}");
+                    return Ok(());
+                }
+
+                if stashes.is_empty() {
+                    println!("No stashes.");
+                }
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or(0);
+                for s in stashes {
+                    let position = format!("stash@{{{}}}", s.index);
+                    let label = s.name.unwrap_or(s.message);
+                    let branch = s.branch.unwrap_or_else(|| "-".to_string());
+                    let files = if s.files.len() > 3 {
+                        format!("{}, ... (+{})", s.files[..3].join(", "), s.files.len() - 3)
+                    } else {
+                        s.files.join(", ")
+                    };
+                    println!(
+                        "{}  {}  on {}  {}  {}",
+                        position.yellow(),
+                        label.bold(),
+                        branch,
+                        format_age(now - s.time).dimmed(),
+                        files
+                    );
+                }
+            }
+            Some(StashCommands::Apply { name }) => unstash(repo, &name, false)?,
+            Some(StashCommands::Pop { name }) => unstash(repo, &name, true)?,
+            Some(StashCommands::Drop { name }) => {
+                let entry = drop_stash(repo, name.as_deref())?;
+                println!(
+                    "--- Dropped stash@{{{}}} ({}) ---",
+                    entry.index,
+                    entry.name.unwrap_or(entry.message)
+                );
+            }
+        },
         Commands::Resolve { cleanup } => {
-            resolve(&repo, cleanup)?;
+            resolve(repo, cleanup)?;
+        }
+        Commands::Undo { force } => {
+            let entry = journal::undo(repo, force)?;
+            println!("--- Undid 'gg {}' ---", entry.command);
+            for (name, old, new) in entry.ref_changes() {
+                let short =
+                    |oid: Option<&str>| oid.map_or("(none)".to_string(), |o| o[..7].to_string());
+                println!("  {name}: {} -> {}", short(new), short(old));
+            }
+            if let Some(backup) = &entry.backup {
+                println!(
+                    "{}: What the undo overwrote is saved in '{backup}' \
+                     ('git checkout {backup} -- .' brings the files back).",
+                    "Note".yellow()
+                );
+            }
+            if !entry.pushed.is_empty() {
+                println!(
+                    "{}: {} still has the pushed commits.",
+                    "Note".yellow(),
+                    entry.pushed.join(", ")
+                );
+            }
         }
         Commands::PR { open } => {
-            let link = if let Some(link) = get_pr_link(&repo) {
+            let link = if let Some(link) = get_pr_link(repo) {
                 link
             } else {
                 return Err(Error::from_str("No PR URL found"));
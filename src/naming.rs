@@ -0,0 +1,167 @@
+use deunicode::deunicode;
+use git2::{BranchType, Error, Repository};
+use regex::Regex;
+
+use crate::helpers::gg_config_str;
+
+const DEFAULT_TEMPLATE: &str = "{type}/{ticket}-{slug}";
+
+/// The kind of work a branch holds, used as its prefix
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BranchKind {
+    Feature,
+    Fix,
+    Chore,
+}
+
+impl BranchKind {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            BranchKind::Feature => "feature",
+            BranchKind::Fix => "fix",
+            BranchKind::Chore => "chore",
+        }
+    }
+}
+
+/// Team-wide branch naming rules, read from the `gg.branch.*` git config keys
+pub struct BranchNamePolicy {
+    /// Template with `{type}`, `{ticket}` and `{slug}` placeholders
+    pub template: String,
+    /// Regex every branch name must match, if any
+    pub pattern: Option<String>,
+}
+
+impl Default for BranchNamePolicy {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_TEMPLATE.to_string(),
+            pattern: None,
+        }
+    }
+}
+
+impl BranchNamePolicy {
+    pub fn from_config(repo: &Repository) -> Self {
+        Self {
+            template: gg_config_str(repo, "gg.branch.template")
+                .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            pattern: gg_config_str(repo, "gg.branch.pattern"),
+        }
+    }
+
+    /// Builds a branch name from free text, e.g. "Add login page" -> "feature/add-login-page"
+    pub fn format(&self, input: &str, kind: BranchKind, ticket: Option<&str>) -> String {
+        // Respect names that already carry a known prefix, e.g. "fix/typo"
+        let (kind, text) = [BranchKind::Feature, BranchKind::Fix, BranchKind::Chore]
+            .into_iter()
+            .find_map(|k| {
+                input
+                    .strip_prefix(k.prefix())
+                    .and_then(|rest| rest.strip_prefix('/'))
+                    .map(|rest| (k, rest))
+            })
+            .unwrap_or((kind, input));
+
+        let ticket = ticket.map(sanitize_ticket).unwrap_or_default();
+        let name = self
+            .template
+            .replace("{type}", kind.prefix())
+            .replace("{ticket}", &ticket)
+            .replace("{slug}", &slugify(text));
+
+        tidy_separators(&name)
+    }
+
+    /// Checks a branch name against the configured pattern
+    pub fn validate(&self, name: &str) -> Result<(), Error> {
+        let Some(pattern) = &self.pattern else {
+            return Ok(());
+        };
+
+        let re = Regex::new(pattern)
+            .map_err(|e| Error::from_str(&format!("Invalid gg.branch.pattern: {e}")))?;
+
+        if re.is_match(name) {
+            Ok(())
+        } else {
+            Err(Error::from_str(&format!(
+                "Branch name '{name}' does not match the naming pattern '{pattern}'"
+            )))
+        }
+    }
+}
+
+/// Turns free text into a branch-safe slug ("Add login page!" -> "add-login-page").
+/// Non-ASCII letters are transliterated ("Café" -> "cafe") rather than dropped.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in deunicode(text).chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+fn sanitize_ticket(ticket: &str) -> String {
+    deunicode(ticket.trim())
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_string()
+}
+
+/// Cleans up separators left behind by empty placeholders (e.g. "feature/-slug")
+fn tidy_separators(name: &str) -> String {
+    name.split('/')
+        .map(|segment| {
+            let mut out = String::new();
+            for c in segment.chars() {
+                let is_sep = c == '-' || c == '_';
+                if is_sep && (out.is_empty() || out.ends_with(['-', '_'])) {
+                    continue;
+                }
+                out.push(c);
+            }
+            out.trim_end_matches(['-', '_']).to_string()
+        })
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Resolves what the user typed into the branch name `gg feature` should use.
+/// Existing branches (local or on origin) are used as-is so they can still be switched to.
+pub fn resolve_branch_name(
+    repo: &Repository,
+    input: &str,
+    kind: BranchKind,
+    ticket: Option<&str>,
+    raw: bool,
+) -> Result<String, Error> {
+    let exists = repo.find_branch(input, BranchType::Local).is_ok()
+        || repo
+            .find_branch(&format!("origin/{input}"), BranchType::Remote)
+            .is_ok();
+    if exists {
+        return Ok(input.to_string());
+    }
+
+    let policy = BranchNamePolicy::from_config(repo);
+    let name = if raw {
+        input.to_string()
+    } else {
+        policy.format(input, kind, ticket)
+    };
+
+    if name.is_empty() {
+        return Err(Error::from_str("Branch name is empty"));
+    }
+    policy.validate(&name)?;
+
+    Ok(name)
+}
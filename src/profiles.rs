@@ -0,0 +1,245 @@
+use git_url_parse::GitUrl;
+use git_url_parse::types::provider::GenericProvider;
+use git2::{Config, ConfigLevel, Error, Repository};
+
+use crate::helpers::{configure_git, gg_config_str, home_dir};
+use crate::security::glob_match;
+
+/// A named identity (`gg creds add`), stored as `gg.profile.<profile>.*` in git config.
+/// A profile applies to a repo when all the kinds of rules it has match: any of its
+/// hosts, any of its owners and any of its paths.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub profile: String,
+    pub name: String,
+    pub email: String,
+    /// Remote hosts (globs), e.g. "github.com" or "*.corp.example"
+    pub hosts: Vec<String>,
+    /// Remote owners/organizations (globs), e.g. "acme"
+    pub owners: Vec<String>,
+    /// Repo locations (globs); a trailing '/' matches everything below, like `includeIf`
+    pub paths: Vec<String>,
+}
+
+impl Profile {
+    fn has_rules(&self) -> bool {
+        !(self.hosts.is_empty() && self.owners.is_empty() && self.paths.is_empty())
+    }
+
+    /// Whether the profile's rules select it for a repo
+    pub fn matches(&self, context: &RepoContext) -> bool {
+        let any = |patterns: &[String], value: Option<&str>, prepare: fn(&str) -> String| {
+            patterns.is_empty()
+                || value.is_some_and(|v| patterns.iter().any(|p| glob_match(&prepare(p), v)))
+        };
+
+        self.has_rules()
+            && any(&self.hosts, context.host.as_deref(), str::to_string)
+            && any(&self.owners, context.owner.as_deref(), str::to_string)
+            && any(&self.paths, context.path.as_deref(), path_pattern)
+    }
+}
+
+/// Expands `~/` and turns a trailing '/' into a prefix match
+fn path_pattern(pattern: &str) -> String {
+    let pattern = match pattern.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest).to_string_lossy().into_owned(),
+        None => pattern.to_string(),
+    };
+    if pattern.ends_with('/') {
+        format!("{pattern}*")
+    } else {
+        pattern
+    }
+}
+
+/// What profile rules are matched against: origin's host and owner, and the repo's location
+#[derive(Debug, Default)]
+pub struct RepoContext {
+    pub host: Option<String>,
+    pub owner: Option<String>,
+    /// The working directory, with a trailing '/'
+    pub path: Option<String>,
+}
+
+impl RepoContext {
+    pub fn of(repo: &Repository) -> Self {
+        let url = repo
+            .find_remote("origin")
+            .ok()
+            .and_then(|r| r.url().map(str::to_string));
+        let parsed = url.as_deref().and_then(|u| GitUrl::parse(u).ok());
+
+        let host = parsed
+            .as_ref()
+            .and_then(|p| p.host().map(str::to_lowercase));
+        let owner = parsed
+            .as_ref()
+            .and_then(|p| p.provider_info::<GenericProvider>().ok())
+            .map(|info| info.owner().to_string());
+        let path = repo.workdir().map(|p| {
+            let path = p.to_string_lossy();
+            format!("{}/", path.trim_end_matches('/'))
+        });
+
+        Self { host, owner, path }
+    }
+}
+
+/// Lists the identity profiles visible from `config`
+pub fn list_profiles(config: &Config) -> Result<Vec<Profile>, Error> {
+    let mut profiles: Vec<Profile> = Vec::new();
+
+    let mut entries = config.entries(Some(r"gg\.profile\..*"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let (Some(key), Some(value)) = (entry.name(), entry.value()) else {
+            continue;
+        };
+        let Some((profile, field)) = key
+            .strip_prefix("gg.profile.")
+            .and_then(|k| k.rsplit_once('.'))
+        else {
+            continue;
+        };
+
+        let index = match profiles.iter().position(|p| p.profile == profile) {
+            Some(index) => index,
+            None => {
+                profiles.push(Profile {
+                    profile: profile.to_string(),
+                    ..Default::default()
+                });
+                profiles.len() - 1
+            }
+        };
+        let entry = &mut profiles[index];
+        let value = value.to_string();
+        match field {
+            "name" => entry.name = value,
+            "email" => entry.email = value,
+            "host" => entry.hosts.push(value),
+            "owner" => entry.owners.push(value),
+            "path" => entry.paths.push(value),
+            _ => {}
+        }
+    }
+
+    profiles.sort_by(|a, b| a.profile.cmp(&b.profile));
+    Ok(profiles)
+}
+
+pub fn find_profile(config: &Config, profile: &str) -> Result<Profile, Error> {
+    list_profiles(config)?
+        .into_iter()
+        .find(|p| p.profile == profile)
+        .ok_or_else(|| {
+            Error::from_str(&format!(
+                "No identity profile named '{profile}'. Create it with 'gg creds add {profile} <name> <email>'."
+            ))
+        })
+}
+
+/// Removes a profile and all its rules
+pub fn remove_profile(config: &mut Config, profile: &str) -> Result<(), Error> {
+    let prefix = format!("gg.profile.{profile}.");
+    let mut keys: Vec<String> = Vec::new();
+    {
+        let mut entries = config.entries(None)?;
+        while let Some(entry) = entries.next() {
+            if let Some(name) = entry?.name()
+                && name.starts_with(&prefix)
+                && !keys.iter().any(|k| k == name)
+            {
+                keys.push(name.to_string());
+            }
+        }
+    }
+
+    if keys.is_empty() {
+        return Err(Error::from_str(&format!(
+            "No identity profile named '{profile}'"
+        )));
+    }
+    for key in keys {
+        config.remove_multivar(&key, ".*")?;
+    }
+    Ok(())
+}
+
+/// Stores a profile, replacing any existing profile of the same name
+pub fn add_profile(config: &mut Config, profile: &Profile) -> Result<(), Error> {
+    if list_profiles(config)?
+        .iter()
+        .any(|p| p.profile == profile.profile)
+    {
+        remove_profile(config, &profile.profile)?;
+    }
+
+    let key = |field: &str| format!("gg.profile.{}.{field}", profile.profile);
+    config.set_str(&key("name"), &profile.name)?;
+    config.set_str(&key("email"), &profile.email)?;
+    for (field, values) in [
+        ("host", &profile.hosts),
+        ("owner", &profile.owners),
+        ("path", &profile.paths),
+    ] {
+        for value in values {
+            // A regex matching no existing value appends another one
+            config.set_multivar(&key(field), "^$", value)?;
+        }
+    }
+    Ok(())
+}
+
+/// The first profile whose rules match the repo
+pub fn matching_profile(repo: &Repository) -> Result<Option<Profile>, Error> {
+    let context = RepoContext::of(repo);
+    Ok(list_profiles(&repo.config()?)?
+        .into_iter()
+        .find(|p| p.matches(&context)))
+}
+
+/// Outcome of checking the commit identity against the profile rules
+#[derive(Debug, PartialEq, Eq)]
+pub enum IdentityCheck {
+    /// No rule applies, or the identity already matches
+    Fine,
+    /// The repo had no identity of its own, so the matching profile was applied to it
+    Applied(Profile),
+    /// The repo's identity differs from the profile its rules select
+    Mismatch {
+        profile: Profile,
+        name: String,
+        email: String,
+    },
+}
+
+/// Makes sure commits in this repo use the identity its profile rules select.
+/// A repo without a local identity gets the matching profile applied; a repo with a
+/// different local identity is reported, as it was probably set on purpose.
+pub fn check_identity(repo: &Repository) -> Result<IdentityCheck, Error> {
+    let Some(profile) = matching_profile(repo)? else {
+        return Ok(IdentityCheck::Fine);
+    };
+
+    let name = gg_config_str(repo, "user.name").unwrap_or_default();
+    let email = gg_config_str(repo, "user.email").unwrap_or_default();
+    if name == profile.name && email == profile.email {
+        return Ok(IdentityCheck::Fine);
+    }
+
+    let local = repo.config()?.open_level(ConfigLevel::Local)?;
+    let local_identity =
+        local.get_entry("user.name").is_ok() || local.get_entry("user.email").is_ok();
+    if local_identity {
+        return Ok(IdentityCheck::Mismatch {
+            profile,
+            name,
+            email,
+        });
+    }
+
+    configure_git(repo, &profile.name, &profile.email, false)?;
+    Ok(IdentityCheck::Applied(profile))
+}
@@ -0,0 +1,291 @@
+use std::cell::{Cell, RefCell};
+use std::io::{IsTerminal, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use git2::PackBuilderStage;
+
+/// How often the live progress line may be redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a plain progress line is written when stdout isn't a terminal
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+thread_local! {
+    /// The message of the `show_progress` step currently running, if any
+    static CURRENT_STEP: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Whether the live display has written over the current step's line
+    static LINE_DIRTY: Cell<bool> = const { Cell::new(false) };
+    /// Messages sent by the remote (e.g. "Create a pull request for..."), not yet shown
+    static REMOTE_MESSAGES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    /// The `show_progress` steps run so far, and whether each succeeded
+    static FINISHED_STEPS: RefCell<Vec<(String, bool)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Records how a `show_progress` step ended
+pub fn finish_step(message: &str, succeeded: bool) {
+    FINISHED_STEPS.with(|s| s.borrow_mut().push((message.to_string(), succeeded)));
+}
+
+/// Takes the steps finished since the last call, oldest first
+pub fn take_finished_steps() -> Vec<(String, bool)> {
+    FINISHED_STEPS.with(|s| std::mem::take(&mut *s.borrow_mut()))
+}
+
+/// Takes the remote messages collected since the last call
+pub fn take_remote_messages() -> Vec<String> {
+    REMOTE_MESSAGES.with(|m| std::mem::take(&mut *m.borrow_mut()))
+}
+
+/// Whether a remote line is just progress chatter ("Counting objects: 100% (3/3), done.")
+fn is_progress_line(line: &str) -> bool {
+    line.contains("% (") || line.ends_with(", done.") || line.starts_with("Total ")
+}
+
+/// Marks the start of a `show_progress` step, so transfers can draw next to it
+pub fn begin_step(message: &str) {
+    CURRENT_STEP.with(|s| *s.borrow_mut() = Some(message.to_string()));
+}
+
+/// Marks the end of a step, restoring its "message... " line if a transfer drew over it
+pub fn end_step() {
+    let step = CURRENT_STEP.with(|s| s.borrow_mut().take());
+    if LINE_DIRTY.with(|d| d.replace(false)) {
+        if std::io::stdout().is_terminal() {
+            print!("\r\x1b[2K{}... ", step.unwrap_or_default());
+        } else {
+            // Plain updates ended with a newline, so start the step's line afresh
+            print!("{}... ", step.unwrap_or_default());
+        }
+        _ = std::io::stdout().flush();
+    }
+}
+
+/// Formats a byte count, e.g. "1.5 MiB"
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Live display of a fetch or push, fed by the git2 remote callbacks.
+/// On a terminal the step's line is redrawn in place; on other outputs (logs, CI) a plain
+/// line is written every few seconds instead, so quick transfers stay quiet there.
+pub struct TransferDisplay {
+    start: Instant,
+    last_draw: Cell<Option<Instant>>,
+    tty: bool,
+    /// Remote output not yet terminated by a newline
+    sideband_buffer: RefCell<String>,
+}
+
+impl TransferDisplay {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Self {
+            start: Instant::now(),
+            last_draw: Cell::new(None),
+            tty: std::io::stdout().is_terminal(),
+            sideband_buffer: RefCell::new(String::new()),
+        })
+    }
+
+    fn throughput(&self, bytes: usize) -> String {
+        let secs = self.start.elapsed().as_secs_f64();
+        if secs < 0.5 {
+            return String::new();
+        }
+        format!(" | {}/s", format_bytes((bytes as f64 / secs) as usize))
+    }
+
+    /// Receiving objects, then resolving deltas (fetch side)
+    pub fn fetch(&self, stats: &git2::Progress) {
+        let received = stats.received_objects();
+        let total = stats.total_objects();
+        let done = received == total && stats.indexed_deltas() == stats.total_deltas();
+
+        let status = if received < total {
+            format!(
+                "Receiving objects: {}% ({received}/{total}), {}{}",
+                percent(received, total),
+                format_bytes(stats.received_bytes()),
+                self.throughput(stats.received_bytes())
+            )
+        } else {
+            format!(
+                "Resolving deltas: {}% ({}/{})",
+                percent(stats.indexed_deltas(), stats.total_deltas()),
+                stats.indexed_deltas(),
+                stats.total_deltas()
+            )
+        };
+        self.draw(&status, done);
+    }
+
+    /// Writing objects (push side)
+    pub fn push(&self, current: usize, total: usize, bytes: usize) {
+        let status = format!(
+            "Writing objects: {}% ({current}/{total}), {}{}",
+            percent(current, total),
+            format_bytes(bytes),
+            self.throughput(bytes)
+        );
+        self.draw(&status, current == total);
+    }
+
+    /// Building the pack to push
+    pub fn pack(&self, stage: PackBuilderStage, current: usize, total: usize) {
+        let label = match stage {
+            PackBuilderStage::AddingObjects => "Counting objects",
+            PackBuilderStage::Deltafication => "Compressing objects",
+        };
+        // The total isn't known while objects are still being counted
+        let status = if total == 0 {
+            format!("{label}: {current}")
+        } else {
+            format!("{label}: {}% ({current}/{total})", percent(current, total))
+        };
+        self.draw(&status, current == total);
+    }
+
+    /// Text sent by the remote: progress is drawn live, complete messages are kept
+    /// for `show_progress` to print once the step is over
+    pub fn sideband(&self, data: &[u8]) {
+        let mut buffer = self.sideband_buffer.borrow_mut();
+        buffer.push_str(&String::from_utf8_lossy(data));
+
+        let (messages, partial) = split_sideband(&mut buffer);
+        REMOTE_MESSAGES.with(|m| m.borrow_mut().extend(messages));
+        if let Some(line) = partial {
+            self.draw(&format!("remote: {line}"), false);
+        }
+    }
+
+    fn draw(&self, status: &str, force: bool) {
+        let now = Instant::now();
+        if !draw_due(self.tty, self.last_draw.get(), self.start, now, force) {
+            return;
+        }
+        self.last_draw.set(Some(now));
+
+        let step = CURRENT_STEP.with(|s| s.borrow().clone());
+        if !self.tty {
+            // End the step's "message... " line before the first update
+            if step.is_some() && !LINE_DIRTY.with(|d| d.replace(true)) {
+                println!();
+            }
+            println!("  {status}");
+            return;
+        }
+        match step {
+            Some(step) => {
+                print!("\r\x1b[2K{step}... {status}");
+                LINE_DIRTY.with(|d| d.set(true));
+            }
+            // Outside of a step there is no line to restore, so finish our own
+            None if force => println!("\r\x1b[2K{status}"),
+            None => print!("\r\x1b[2K{status}"),
+        }
+        _ = std::io::stdout().flush();
+    }
+}
+
+/// Whether an update should be shown now. Terminals redraw often, and always for the
+/// final update; other outputs get a line at most every `PLAIN_INTERVAL`.
+fn draw_due(tty: bool, last: Option<Instant>, start: Instant, now: Instant, force: bool) -> bool {
+    if tty {
+        force || last.is_none_or(|last| now.duration_since(last) >= REDRAW_INTERVAL)
+    } else {
+        now.duration_since(last.unwrap_or(start)) >= PLAIN_INTERVAL
+    }
+}
+
+/// Takes the complete lines out of the remote's output, returning the messages worth
+/// keeping (progress chatter dropped) and the latest update of the unfinished line.
+/// Only the last '\r'-separated update of a line is what the remote meant to say.
+fn split_sideband(buffer: &mut String) -> (Vec<String>, Option<String>) {
+    let mut messages = Vec::new();
+    while let Some(end) = buffer.find('\n') {
+        let line: String = buffer.drain(..=end).collect();
+        let line = line.rsplit('\r').map(str::trim).find(|l| !l.is_empty());
+        if let Some(line) = line
+            && !is_progress_line(line)
+        {
+            messages.push(line.to_string());
+        }
+    }
+
+    let partial = buffer
+        .split('\r')
+        .map(str::trim)
+        .rfind(|l| !l.is_empty())
+        .map(str::to_string);
+    (messages, partial)
+}
+
+fn percent(current: usize, total: usize) -> usize {
+    (current * 100).checked_div(total).unwrap_or(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_bytes_and_percentages() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024 * 1024), "3072.0 GiB");
+
+        assert_eq!(percent(1, 3), 33);
+        assert_eq!(percent(3, 3), 100);
+        // Nothing to transfer counts as done
+        assert_eq!(percent(0, 0), 100);
+    }
+
+    #[test]
+    fn splits_sideband_lines() {
+        let mut buffer = String::from("remote: Create a pull request:\nremote: Coun");
+        let (messages, partial) = split_sideband(&mut buffer);
+        assert_eq!(messages, ["remote: Create a pull request:"]);
+        assert_eq!(partial.as_deref(), Some("remote: Coun"));
+        assert_eq!(buffer, "remote: Coun");
+
+        // '\r' updates replace each other, and finished progress is dropped
+        buffer.push_str("ting objects: 50% (1/2)\rCounting objects: 100% (2/2), done.\n");
+        let (messages, partial) = split_sideband(&mut buffer);
+        assert!(messages.is_empty());
+        assert_eq!(partial, None);
+
+        buffer.push_str("Resolving: 10%\rResolving: 60%\r");
+        let (_, partial) = split_sideband(&mut buffer);
+        assert_eq!(partial.as_deref(), Some("Resolving: 60%"));
+    }
+
+    #[test]
+    fn redraws_terminals_often_and_other_outputs_rarely() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // Terminal: first update, then throttled, but the final one always shows
+        assert!(draw_due(true, None, start, at(10), false));
+        assert!(!draw_due(true, Some(at(10)), start, at(50), false));
+        assert!(draw_due(true, Some(at(10)), start, at(120), false));
+        assert!(draw_due(true, Some(at(10)), start, at(20), true));
+
+        // Not a terminal: quick transfers print nothing, long ones a line now and then
+        assert!(!draw_due(false, None, start, at(1_000), true));
+        assert!(draw_due(false, None, start, at(5_000), false));
+        assert!(!draw_due(false, Some(at(5_000)), start, at(8_000), false));
+        assert!(draw_due(false, Some(at(5_000)), start, at(10_000), false));
+    }
+}
@@ -0,0 +1,140 @@
+use git2::{Error, Repository};
+use serde::Serialize;
+
+use crate::git_commands::push;
+use crate::helpers::show_progress;
+use crate::link::{SyncStrategy, sync_unrelated_histories};
+
+/// The hosting service behind a remote URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    Bitbucket,
+    Azure,
+    /// A path on this machine
+    Local,
+    Other,
+}
+
+impl Forge {
+    pub fn name(self) -> &'static str {
+        match self {
+            Forge::GitHub => "GitHub",
+            Forge::GitLab => "GitLab",
+            Forge::Bitbucket => "Bitbucket",
+            Forge::Azure => "Azure DevOps",
+            Forge::Local => "local",
+            Forge::Other => "other",
+        }
+    }
+}
+
+/// Works out the forge from a remote URL's host
+pub fn detect_forge(url: &str) -> Forge {
+    let host = match url.split_once("://") {
+        Some(("file", _)) => return Forge::Local,
+        Some((_, rest)) => rest.split('/').next().unwrap_or_default(),
+        // scp-like "git@host:path", or a plain path
+        None => match url.split_once(':') {
+            Some((authority, _)) if !authority.contains('/') => authority,
+            _ => return Forge::Local,
+        },
+    };
+    let host = host.rsplit('@').next().unwrap_or_default().to_lowercase();
+
+    if host.contains("github") {
+        Forge::GitHub
+    } else if host.contains("gitlab") {
+        Forge::GitLab
+    } else if host.contains("bitbucket") {
+        Forge::Bitbucket
+    } else if host.contains("dev.azure.com") || host.contains("visualstudio.com") {
+        Forge::Azure
+    } else {
+        Forge::Other
+    }
+}
+
+/// A remote, as shown by `gg remote list`
+#[derive(Debug, Serialize)]
+pub struct RemoteInfo {
+    pub name: String,
+    pub fetch_url: Option<String>,
+    /// Where pushes go; the fetch URL unless `set-push-url` changed it
+    pub push_url: Option<String>,
+    pub forge: Forge,
+}
+
+pub fn list_remotes(repo: &Repository) -> Result<Vec<RemoteInfo>, Error> {
+    let mut remotes = Vec::new();
+    for name in repo.remotes()?.iter().flatten() {
+        let remote = repo.find_remote(name)?;
+        let fetch_url = remote.url().map(str::to_string);
+        let push_url = remote
+            .pushurl()
+            .map(str::to_string)
+            .or_else(|| fetch_url.clone());
+        remotes.push(RemoteInfo {
+            name: name.to_string(),
+            forge: fetch_url.as_deref().map_or(Forge::Other, detect_forge),
+            fetch_url,
+            push_url,
+        });
+    }
+    Ok(remotes)
+}
+
+/// Sets the URL of a remote, creating it if needed, then (with `sync`) joins the
+/// remote's history with the local one and pushes the current branch
+pub fn add_remote(
+    repo: &Repository,
+    name: &str,
+    url: &str,
+    sync: bool,
+    strategy: Option<SyncStrategy>,
+) -> Result<(), Error> {
+    match repo.find_remote(name) {
+        Ok(_) => repo.remote_set_url(name, url)?,
+        Err(_) => {
+            repo.remote(name, url)?;
+        }
+    }
+    println!("--- Remote '{name}' set to {url} ---");
+
+    if !sync {
+        return Ok(());
+    }
+
+    println!("--- Syncing with remote ---");
+    if let Err(e) = sync_unrelated_histories(repo, name, strategy) {
+        // Not fatal: the remote URL is still set successfully
+        eprintln!("--- Sync Note: {e} ---");
+    } else {
+        let head = repo.head()?;
+        let branch_name = head.shorthand().unwrap_or("HEAD");
+        show_progress("Pushing", || push(repo, name, branch_name, false))?;
+    }
+    Ok(())
+}
+
+/// Renames a remote, moving its tracking branches and the upstreams that use it.
+/// Returns the custom fetch refspecs git could not rename, which need fixing by hand.
+pub fn rename_remote(repo: &Repository, old: &str, new: &str) -> Result<Vec<String>, Error> {
+    repo.find_remote(old)?;
+    let problems = repo.remote_rename(old, new)?;
+    Ok(problems.iter().flatten().map(str::to_string).collect())
+}
+
+/// Deletes a remote along with its tracking branches and the upstreams that use it
+pub fn remove_remote(repo: &Repository, name: &str) -> Result<(), Error> {
+    repo.find_remote(name)?;
+    repo.remote_delete(name)
+}
+
+/// Sends pushes to a different URL than fetches
+pub fn set_push_url(repo: &Repository, name: &str, url: &str) -> Result<(), Error> {
+    repo.find_remote(name)?;
+    repo.remote_set_pushurl(name, Some(url))
+}
@@ -0,0 +1,290 @@
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use git2::cert::{Cert, SshHostKeyType};
+use git2::{CertificateCheckStatus, Config, Error};
+use hmac::{Hmac, Mac};
+use owo_colors::OwoColorize;
+use sha1::Sha1;
+
+use crate::ssh::{connection_port, user_known_hosts_files};
+
+/// The known_hosts file shared by every user of the machine
+const SYSTEM_KNOWN_HOSTS: &str = "/etc/ssh/ssh_known_hosts";
+
+/// What the known_hosts files say about a host key
+#[derive(Debug, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    /// The host is listed with this exact key
+    Known,
+    /// The host is listed, but with a different key: possibly a man-in-the-middle
+    Mismatch,
+    /// The key was explicitly revoked (`@revoked`)
+    Revoked,
+    /// The host isn't listed at all
+    Unknown,
+}
+
+/// Decides whether to trust a remote's TLS certificate or SSH host key.
+/// TLS is left to libgit2's own verification; SSH keys are checked against the user's
+/// known_hosts files (`UserKnownHostsFile`, `~/.ssh/known_hosts` by default) and
+/// `/etc/ssh/ssh_known_hosts`, offering trust-on-first-use for unknown hosts.
+/// Hosts listed in the `gg.insecureHost` config are trusted without checks.
+pub fn check_certificate(cert: &Cert<'_>, host: &str) -> Result<CertificateCheckStatus, Error> {
+    if is_insecure_host(host) {
+        eprintln!(
+            "{}: Skipping certificate verification for '{host}' (gg.insecureHost)",
+            "Warning".yellow()
+        );
+        return Ok(CertificateCheckStatus::CertificateOk);
+    }
+
+    let Some(hostkey) = cert.as_hostkey() else {
+        // TLS: let libgit2 verify the certificate chain and hostname
+        return Ok(CertificateCheckStatus::CertificatePassthrough);
+    };
+
+    let (Some(key), Some(key_type)) = (hostkey.hostkey(), hostkey.hostkey_type()) else {
+        return Ok(CertificateCheckStatus::CertificatePassthrough);
+    };
+
+    let port = connection_port(host);
+    let user_files = user_known_hosts_files(host);
+    let mut files = user_files.clone();
+    files.push(PathBuf::from(SYSTEM_KNOWN_HOSTS));
+    let fingerprint = hostkey
+        .hash_sha256()
+        .map(|h| format!("SHA256:{}", STANDARD_NO_PAD.encode(h)))
+        .unwrap_or_default();
+
+    let (status, file) = lookup_in_files(&files, host, port, key_type.name(), key);
+    let file = file.unwrap_or(&user_files[0]).display();
+    match status {
+        HostKeyStatus::Known => Ok(CertificateCheckStatus::CertificateOk),
+        HostKeyStatus::Mismatch => Err(Error::from_str(&format!(
+            "The host key for '{host}' has changed ({fingerprint}). This could mean someone is \
+             intercepting your connection. If the change is expected, remove the old entry from {file}."
+        ))),
+        HostKeyStatus::Revoked => Err(Error::from_str(&format!(
+            "The host key for '{host}' has been revoked in {file}."
+        ))),
+        HostKeyStatus::Unknown => {
+            if !confirm_new_host(host, key_type, &fingerprint) {
+                return Err(Error::from_str(&format!(
+                    "Host key verification failed: '{host}' is not in {file}."
+                )));
+            }
+            let line = format!(
+                "{} {} {}\n",
+                known_hosts_name(host, port),
+                key_type.name(),
+                STANDARD.encode(key)
+            );
+            append_known_host(&user_files[0], &line)
+                .map_err(|e| Error::from_str(&format!("Failed to update known_hosts: {e}")))?;
+            Ok(CertificateCheckStatus::CertificateOk)
+        }
+    }
+}
+
+/// Looks a host key up in several known_hosts files. A revocation anywhere wins, then a
+/// file listing the key; returns the file the verdict came from.
+fn lookup_in_files<'a>(
+    files: &'a [PathBuf],
+    host: &str,
+    port: u16,
+    key_type: &str,
+    key: &[u8],
+) -> (HostKeyStatus, Option<&'a PathBuf>) {
+    let mut result = (HostKeyStatus::Unknown, None);
+    for file in files {
+        let contents = std::fs::read_to_string(file).unwrap_or_default();
+        match lookup_host_key(&contents, host, port, key_type, key) {
+            HostKeyStatus::Revoked => return (HostKeyStatus::Revoked, Some(file)),
+            HostKeyStatus::Known => result = (HostKeyStatus::Known, Some(file)),
+            HostKeyStatus::Mismatch if result.0 == HostKeyStatus::Unknown => {
+                result = (HostKeyStatus::Mismatch, Some(file));
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Whether `host` is in the `gg.insecureHost` allowlist
+fn is_insecure_host(host: &str) -> bool {
+    let Ok(config) = Config::open_default() else {
+        return false;
+    };
+    let Ok(mut entries) = config.multivar("gg.insecureHost", None) else {
+        return false;
+    };
+
+    let mut found = false;
+    while let Some(Ok(entry)) = entries.next() {
+        if entry.value() == Some(host) {
+            found = true;
+        }
+    }
+    found
+}
+
+/// Asks whether to trust a host seen for the first time. Never trusts without a terminal.
+fn confirm_new_host(host: &str, key_type: SshHostKeyType, fingerprint: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+
+    print!(
+        "\nThe authenticity of host '{}' can't be established.\n\
+        {} key fingerprint is {fingerprint}.\n\
+        Trust it and add it to known_hosts? [y/N]: ",
+        host.bold(),
+        key_type.name()
+    );
+    _ = io::stdout().flush();
+
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn append_known_host(path: &PathBuf, line: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+/// The name known_hosts lists a host under: plain on the default port, `[host]:port` otherwise
+fn known_hosts_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{host}]:{port}")
+    }
+}
+
+/// Looks up a host key of type `key_type` (e.g. "ssh-ed25519") in the contents of a
+/// known_hosts file. Like ssh, entries of another type don't count against the key: a host
+/// that starts offering a new type of key is unknown for it, not mismatched.
+pub fn lookup_host_key(
+    known_hosts: &str,
+    host: &str,
+    port: u16,
+    key_type: &str,
+    key: &[u8],
+) -> HostKeyStatus {
+    let name = known_hosts_name(host, port);
+    let mut status = HostKeyStatus::Unknown;
+
+    for line in known_hosts.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let Some(mut patterns) = fields.next() else {
+            continue;
+        };
+        let mut revoked = false;
+        if patterns.starts_with('@') {
+            // Certificate authorities aren't supported; revocations are honored
+            revoked = patterns == "@revoked";
+            if !revoked {
+                continue;
+            }
+            let Some(p) = fields.next() else { continue };
+            patterns = p;
+        }
+        let (Some(entry_type), Some(encoded)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+
+        if !host_matches(patterns, &name) {
+            continue;
+        }
+
+        let same_key = STANDARD.decode(encoded).is_ok_and(|k| k == key);
+        if revoked {
+            if same_key {
+                return HostKeyStatus::Revoked;
+            }
+        } else if same_key {
+            status = HostKeyStatus::Known;
+        } else if entry_type == key_type && status == HostKeyStatus::Unknown {
+            status = HostKeyStatus::Mismatch;
+        }
+    }
+
+    status
+}
+
+/// Matches a host against a known_hosts pattern list (plain, `[host]:port`, globs, `!negation`
+/// or hashed `|1|salt|hash` entries). `name` is the host as `known_hosts_name` gives it, so
+/// `[host]:port` entries only match on their own port.
+fn host_matches(patterns: &str, name: &str) -> bool {
+    if let Some(hashed) = patterns.strip_prefix("|1|") {
+        let Some((salt, hash)) = hashed.split_once('|') else {
+            return false;
+        };
+        let (Ok(salt), Ok(hash)) = (STANDARD.decode(salt), STANDARD.decode(hash)) else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+            return false;
+        };
+        mac.update(name.as_bytes());
+        return mac.verify_slice(&hash).is_ok();
+    }
+
+    let mut matched = false;
+    for pattern in patterns.split(',') {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(p) => (true, p),
+            None => (false, pattern),
+        };
+
+        if glob_match(pattern, name) {
+            if negated {
+                return false;
+            }
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Minimal `*`/`?` glob matching, as used in known_hosts patterns
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,413 @@
+use std::cell::RefCell;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use git2::{Cred, Error, Remote, Repository};
+
+use crate::helpers::home_dir;
+use crate::security::glob_match;
+
+thread_local! {
+    /// Hosts reached through a `~/.ssh/config` alias, as (real host, alias)
+    static HOST_ALIASES: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+    /// Hosts reached on a port other than 22, as (host, port)
+    static HOST_PORTS: RefCell<Vec<(String, u16)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The settings `~/.ssh/config` gives for one host
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SshHostConfig {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_files: Vec<PathBuf>,
+    pub known_hosts_files: Vec<PathBuf>,
+}
+
+/// Expands a leading `~` to the home directory
+fn expand_tilde(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest),
+        None if path == "~" => home_dir(),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Reads the settings for `host` from the user's `~/.ssh/config`
+pub fn host_config(host: &str) -> SshHostConfig {
+    let contents =
+        std::fs::read_to_string(home_dir().join(".ssh").join("config")).unwrap_or_default();
+    parse_ssh_config(&contents, host)
+}
+
+/// Parses the contents of an ssh_config file for the settings that apply to `host`.
+/// Like ssh, the first value found for a keyword wins, except `IdentityFile`, which accumulates.
+pub fn parse_ssh_config(contents: &str, host: &str) -> SshHostConfig {
+    let mut config = SshHostConfig::default();
+    // Settings before the first Host line apply to every host
+    let mut active = true;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // "Keyword value", "Keyword=value" or "Keyword = value"
+        let Some((keyword, value)) = line.split_once(['=', ' ', '\t']) else {
+            continue;
+        };
+        let value = value.trim_start();
+        let value = value.strip_prefix('=').unwrap_or(value).trim();
+        let (keyword, value) = (keyword.to_lowercase(), value.trim_matches('"'));
+
+        match keyword.as_str() {
+            "host" => active = host_pattern_matches(value, host),
+            // Match blocks need ssh itself to evaluate; ignore them
+            "match" => active = false,
+            _ if !active => {}
+            "hostname" if config.hostname.is_none() => {
+                config.hostname = Some(value.replace("%h", host));
+            }
+            "user" if config.user.is_none() => config.user = Some(value.to_string()),
+            "port" if config.port.is_none() => config.port = value.parse().ok(),
+            "identityfile" => {
+                let path = value.replace("%d", &home_dir().to_string_lossy());
+                config.identity_files.push(expand_tilde(&path));
+            }
+            "userknownhostsfile" if config.known_hosts_files.is_empty() => {
+                config.known_hosts_files = value.split_whitespace().map(expand_tilde).collect();
+            }
+            _ => {}
+        }
+    }
+
+    config
+}
+
+/// Matches a `Host` line's space-separated patterns, honoring `!negation`
+fn host_pattern_matches(patterns: &str, host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split_whitespace() {
+        match pattern.strip_prefix('!') {
+            Some(negated) if glob_match(negated, host) => return false,
+            Some(_) => {}
+            None if glob_match(pattern, host) => matched = true,
+            None => {}
+        }
+    }
+    matched
+}
+
+/// Extracts the identity files passed to ssh in `core.sshCommand`/`GIT_SSH_COMMAND`
+/// (`-i path`, `-ipath` or `-o IdentityFile=path`)
+pub fn identity_flags(command: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut args = command.split_whitespace();
+    while let Some(arg) = args.next() {
+        if arg == "-i" {
+            if let Some(path) = args.next() {
+                files.push(expand_tilde(path.trim_matches(['"', '\''])));
+            }
+        } else if let Some(path) = arg.strip_prefix("-i") {
+            files.push(expand_tilde(path.trim_matches(['"', '\''])));
+        } else if arg == "-o"
+            && let Some(option) = args.next()
+            && let Some((key, value)) = option.split_once('=')
+            && key.eq_ignore_ascii_case("IdentityFile")
+        {
+            files.push(expand_tilde(value.trim_matches(['"', '\''])));
+        }
+    }
+    files
+}
+
+/// The parts of an SSH remote URL (`ssh://user@host:port/path` or `user@host:path`)
+#[derive(Debug, PartialEq, Eq)]
+pub struct SshUrl {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+}
+
+pub fn parse_ssh_url(url: &str) -> Option<SshUrl> {
+    let split_user = |s: &str| match s.split_once('@') {
+        Some((user, host)) => (Some(user.to_string()), host.to_string()),
+        None => (None, s.to_string()),
+    };
+
+    if let Some(rest) = url
+        .strip_prefix("ssh://")
+        .or_else(|| url.strip_prefix("git+ssh://"))
+    {
+        let (authority, path) = rest.split_once('/')?;
+        let (user, host_port) = split_user(authority);
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), Some(port.parse().ok()?)),
+            None => (host_port, None),
+        };
+        return Some(SshUrl {
+            user,
+            host,
+            port,
+            path: format!("/{path}"),
+        });
+    }
+
+    // scp-like syntax: no scheme, and a ':' before any '/'
+    if url.contains("://") {
+        return None;
+    }
+    let (authority, path) = url.split_once(':')?;
+    if authority.contains('/') || authority.is_empty() {
+        return None;
+    }
+    let (user, host) = split_user(authority);
+    Some(SshUrl {
+        user,
+        host,
+        port: None,
+        path: path.to_string(),
+    })
+}
+
+/// Rewrites an SSH URL whose host is an alias in `~/.ssh/config` (a different `Hostname`
+/// or `Port`), since libgit2 connects to the host in the URL as-is
+pub fn rewrite_ssh_url(url: &str, config: &SshHostConfig) -> Option<String> {
+    let parsed = parse_ssh_url(url)?;
+    if config.hostname.is_none() && config.port.is_none() {
+        return None;
+    }
+
+    let host = config.hostname.as_deref().unwrap_or(&parsed.host);
+    let user = parsed
+        .user
+        .as_deref()
+        .or(config.user.as_deref())
+        .map(|u| format!("{u}@"))
+        .unwrap_or_default();
+    let port = parsed
+        .port
+        .or(config.port)
+        .map(|p| format!(":{p}"))
+        .unwrap_or_default();
+    let path = if parsed.path.starts_with('/') {
+        parsed.path
+    } else {
+        format!("/{}", parsed.path)
+    };
+
+    Some(format!("ssh://{user}{host}{port}{path}"))
+}
+
+/// Whether a private key file is protected by a passphrase
+pub fn is_key_encrypted(path: &Path) -> bool {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return false;
+    };
+
+    // Legacy PEM keys say so in their headers
+    if contents.contains("ENCRYPTED") {
+        return true;
+    }
+
+    // OpenSSH keys: "openssh-key-v1\0", then the cipher name as a length-prefixed string
+    let body: String = contents
+        .lines()
+        .filter(|l| !l.starts_with("-----"))
+        .collect();
+    let Ok(bytes) = STANDARD.decode(body.trim()) else {
+        return false;
+    };
+    let magic = b"openssh-key-v1\0";
+    let Some(rest) = bytes.strip_prefix(magic.as_slice()) else {
+        return false;
+    };
+    let Some(len) = rest.get(..4) else {
+        return false;
+    };
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    rest.get(4..4 + len).is_some_and(|cipher| cipher != b"none")
+}
+
+/// Asks for the passphrase of a key on the terminal, without echoing it.
+/// Returns `None` when there is no terminal to ask on, or its echo can't be turned off.
+pub fn prompt_passphrase(key: &Path) -> Option<String> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+
+    println!();
+    rpassword::prompt_password(format!("Enter passphrase for key '{}': ", key.display())).ok()
+}
+
+/// The private keys to try for `host`, in order: those given to ssh via
+/// `core.sshCommand`/`GIT_SSH_COMMAND`, those from `~/.ssh/config`, then the default keys
+pub fn identity_candidates(ssh_command: Option<&str>, config: &SshHostConfig) -> Vec<PathBuf> {
+    let dot_ssh = home_dir().join(".ssh");
+    let defaults = ["id_ed25519", "id_rsa", "id_ecdsa"].map(|k| dot_ssh.join(k));
+
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    let command_keys = ssh_command.map(identity_flags).unwrap_or_default();
+    for path in command_keys
+        .into_iter()
+        .chain(config.identity_files.iter().cloned())
+        .chain(defaults)
+    {
+        if path.exists() && !candidates.contains(&path) {
+            candidates.push(path);
+        }
+    }
+    candidates
+}
+
+/// Opens a remote by name. When its URL uses a `~/.ssh/config` alias with its own
+/// `Hostname`/`Port`, an anonymous remote pointing at the real host is returned instead.
+pub fn open_remote<'r>(repo: &'r Repository, name: &str) -> Result<Remote<'r>, Error> {
+    let remote = repo.find_remote(name)?;
+    let Some(url) = remote.url() else {
+        return Ok(remote);
+    };
+    let Some(parsed) = parse_ssh_url(url) else {
+        return Ok(remote);
+    };
+    let Some(rewritten) = rewrite_ssh_url(url, &host_config(&parsed.host)) else {
+        note_port(&parsed);
+        return Ok(remote);
+    };
+
+    if let Some(real) = parse_ssh_url(&rewritten) {
+        note_port(&real);
+        HOST_ALIASES.with(|a| a.borrow_mut().push((real.host, parsed.host)));
+    }
+    repo.remote_anonymous(&rewritten)
+}
+
+fn note_port(url: &SshUrl) {
+    if let Some(port) = url.port {
+        HOST_PORTS.with(|p| p.borrow_mut().push((url.host.clone(), port)));
+    }
+}
+
+/// The port we connected to `host` on, as opened by `open_remote`
+pub(crate) fn connection_port(host: &str) -> u16 {
+    HOST_PORTS.with(|p| {
+        p.borrow()
+            .iter()
+            .rev()
+            .find(|(h, _)| h == host)
+            .map_or(22, |&(_, port)| port)
+    })
+}
+
+/// The known_hosts files of the user for `host`: its `UserKnownHostsFile`, or
+/// `~/.ssh/known_hosts`. New hosts are added to the first one.
+pub(crate) fn user_known_hosts_files(host: &str) -> Vec<PathBuf> {
+    let files = host_config(&config_host(host)).known_hosts_files;
+    if files.is_empty() {
+        vec![home_dir().join(".ssh").join("known_hosts")]
+    } else {
+        files
+    }
+}
+
+/// The name `~/.ssh/config` knows a host by: its alias if we connected through one
+fn config_host(host: &str) -> String {
+    HOST_ALIASES.with(|a| {
+        a.borrow()
+            .iter()
+            .rev()
+            .find(|(real, _)| real == host)
+            .map(|(_, alias)| alias.clone())
+            .unwrap_or_else(|| host.to_string())
+    })
+}
+
+/// Hands out SSH credentials one at a time, as libgit2 asks for them after each failure:
+/// the agent first, then every candidate key, prompting for passphrases where needed.
+pub struct SshAuth {
+    ssh_command: Option<String>,
+    host: Option<String>,
+    config: SshHostConfig,
+    agent_tried: bool,
+    candidates: Vec<PathBuf>,
+    tried: Vec<String>,
+}
+
+impl SshAuth {
+    /// `ssh_command` is the command git would run (`GIT_SSH_COMMAND` or `core.sshCommand`)
+    pub fn new(ssh_command: Option<String>) -> Self {
+        Self {
+            ssh_command,
+            host: None,
+            config: SshHostConfig::default(),
+            agent_tried: false,
+            candidates: Vec::new(),
+            tried: Vec::new(),
+        }
+    }
+
+    /// Loads the settings for the URL's host the first time it is seen
+    fn load(&mut self, url: &str) {
+        let host = parse_ssh_url(url).map(|u| u.host).unwrap_or_default();
+        if self.host.as_deref() == Some(host.as_str()) {
+            return;
+        }
+        self.config = host_config(&config_host(&host));
+        self.candidates = identity_candidates(self.ssh_command.as_deref(), &self.config);
+        self.candidates.reverse();
+        self.host = Some(host);
+    }
+
+    /// The user to log in as: the one in the URL, then `User` from `~/.ssh/config`, then "git"
+    pub fn username(&mut self, url: &str, username_from_url: Option<&str>) -> String {
+        self.load(url);
+        username_from_url
+            .or(self.config.user.as_deref())
+            .unwrap_or("git")
+            .to_string()
+    }
+
+    /// The next credential to try, or an error listing everything tried once none are left
+    pub fn next_key(&mut self, url: &str, username_from_url: Option<&str>) -> Result<Cred, Error> {
+        let user = self.username(url, username_from_url);
+
+        if !self.agent_tried {
+            self.agent_tried = true;
+            self.tried.push("the SSH agent".to_string());
+            return Cred::ssh_key_from_agent(&user);
+        }
+
+        while let Some(key) = self.candidates.pop() {
+            let passphrase = if is_key_encrypted(&key) {
+                match prompt_passphrase(&key) {
+                    Some(passphrase) => Some(passphrase),
+                    None => {
+                        self.tried.push(format!(
+                            "{} (encrypted, no terminal to ask for its passphrase)",
+                            key.display()
+                        ));
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+
+            self.tried.push(key.display().to_string());
+            let public = PathBuf::from(format!("{}.pub", key.display()));
+            let public = public.exists().then_some(public);
+            return Cred::ssh_key(&user, public.as_deref(), &key, passphrase.as_deref());
+        }
+
+        Err(Error::from_str(&format!(
+            "SSH authentication failed for '{user}@{}'. Tried: {}. \
+             Add the right key to your agent, or set IdentityFile in ~/.ssh/config.",
+            self.host.as_deref().unwrap_or_default(),
+            self.tried.join(", ")
+        )))
+    }
+}
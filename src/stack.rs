@@ -0,0 +1,262 @@
+use git2::{BranchType, Error, Oid, RebaseOptions, Repository};
+use owo_colors::OwoColorize;
+
+use crate::git_commands::{fetch, push_with_lease};
+use crate::helpers::{committer_signature, has_remote, main_branch_name, show_progress};
+
+/// Returns the branch a stacked branch was created on top of, if recorded
+pub fn branch_parent(repo: &Repository, name: &str) -> Option<String> {
+    repo.config()
+        .and_then(|mut c| c.snapshot())
+        .and_then(|c| c.get_string(&format!("branch.{name}.ggParent")))
+        .ok()
+}
+
+/// Returns the commit of the parent a stacked branch was last based on
+fn branch_base(repo: &Repository, name: &str) -> Option<Oid> {
+    repo.config()
+        .and_then(|mut c| c.snapshot())
+        .and_then(|c| c.get_string(&format!("branch.{name}.ggBase")))
+        .ok()
+        .and_then(|s| Oid::from_str(&s).ok())
+}
+
+/// Records `parent` (at commit `base`) as the parent of branch `name`
+pub fn set_branch_parent(
+    repo: &Repository,
+    name: &str,
+    parent: &str,
+    base: Oid,
+) -> Result<(), Error> {
+    let mut config = repo.config()?;
+    config.set_str(&format!("branch.{name}.ggParent"), parent)?;
+    config.set_str(&format!("branch.{name}.ggBase"), &base.to_string())?;
+    Ok(())
+}
+
+/// Forgets the parent of a branch, e.g. once it sits directly on main again
+pub fn clear_branch_parent(repo: &Repository, name: &str) -> Result<(), Error> {
+    let mut config = repo.config()?;
+    for key in ["ggParent", "ggBase"] {
+        match config.remove(&format!("branch.{name}.{key}")) {
+            Err(e) if e.code() != git2::ErrorCode::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Records where `name` now sits: stacked on `parent`, or directly on main
+fn update_branch_parent(
+    repo: &Repository,
+    name: &str,
+    parent: &str,
+    base: Oid,
+) -> Result<(), Error> {
+    if parent == main_branch_name(repo) {
+        clear_branch_parent(repo, name)
+    } else {
+        set_branch_parent(repo, name, parent, base)
+    }
+}
+
+/// Lists stacked branches with their parents, ordered so every parent comes before its children
+fn stacked_branches(repo: &Repository) -> Result<Vec<(String, String)>, Error> {
+    let mut stacked = Vec::new();
+    for b in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = b?;
+        if let Some(name) = branch.name()?
+            && let Some(parent) = branch_parent(repo, name)
+        {
+            stacked.push((name.to_string(), parent));
+        }
+    }
+
+    // Depth in the stack: branches on main are 0, their children 1, and so on
+    let depth = |name: &str| {
+        let mut depth = 0;
+        let mut current = name;
+        while let Some((_, parent)) = stacked.iter().find(|(n, _)| n == current) {
+            depth += 1;
+            current = parent;
+            // Guard against cycles in the recorded parents
+            if depth > stacked.len() {
+                break;
+            }
+        }
+        depth
+    };
+
+    let mut ordered = stacked.clone();
+    ordered.sort_by_cached_key(|(name, _)| depth(name));
+    Ok(ordered)
+}
+
+/// The most up to date commit of the main branch, local or on origin
+fn main_tip(repo: &Repository, main_branch: &str) -> Result<Oid, Error> {
+    let local = repo
+        .find_branch(main_branch, BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id();
+
+    let remote = repo
+        .find_branch(&format!("origin/{main_branch}"), BranchType::Remote)
+        .ok()
+        .and_then(|b| b.get().target());
+
+    match remote {
+        Some(remote) if repo.graph_descendant_of(remote, local)? => Ok(remote),
+        _ => Ok(local),
+    }
+}
+
+/// Rebases the commits of `name` after `upstream` onto `onto`, without touching the working tree.
+/// Returns the new tip, or `None` if the rebase hit conflicts and was aborted.
+fn rebase_branch(
+    repo: &Repository,
+    name: &str,
+    upstream: Oid,
+    onto: Oid,
+) -> Result<Option<Oid>, Error> {
+    let branch_ref = repo.find_reference(&format!("refs/heads/{name}"))?;
+    let branch = repo.reference_to_annotated_commit(&branch_ref)?;
+    let upstream = repo.find_annotated_commit(upstream)?;
+    let onto = repo.find_annotated_commit(onto)?;
+
+    let mut opts = RebaseOptions::new();
+    opts.inmemory(true);
+    let mut rebase = repo.rebase(Some(&branch), Some(&upstream), Some(&onto), Some(&mut opts))?;
+
+    let committer = committer_signature(repo)?;
+    let mut tip = onto.id();
+    while let Some(op) = rebase.next() {
+        op?;
+        if rebase.inmemory_index()?.has_conflicts() {
+            rebase.abort()?;
+            return Ok(None);
+        }
+        match rebase.commit(None, &committer, None) {
+            Ok(oid) => tip = oid,
+            // The change is already in the new base, nothing to replay
+            Err(e) if e.code() == git2::ErrorCode::Applied => {}
+            Err(e) => return Err(e),
+        }
+    }
+    rebase.finish(None)?;
+
+    Ok(Some(tip))
+}
+
+/// Rebases every stacked branch onto the current tip of its parent, or onto main
+/// once the parent has been merged or deleted (at which point it is no longer stacked).
+/// Branches that were pushed are force-pushed with a lease.
+pub fn restack(repo: &Repository) -> Result<(), Error> {
+    let remote_exists = has_remote(repo, "origin");
+    if remote_exists {
+        show_progress("Fetching origin", || fetch(repo, "origin", false))?;
+    }
+
+    let main_branch = main_branch_name(repo);
+    let main_oid = main_tip(repo, main_branch)?;
+    let head_name = repo.head()?.shorthand().map(str::to_string);
+
+    let mut failed: Vec<String> = Vec::new();
+    for (name, parent) in stacked_branches(repo)? {
+        if failed.contains(&parent) {
+            println!(
+                "  - Skipping '{}': its parent could not be restacked",
+                name.bold()
+            );
+            failed.push(name);
+            continue;
+        }
+
+        // Work out the new parent: stay on it while it lives, fall back to main once merged
+        let parent_oid = repo
+            .find_branch(&parent, BranchType::Local)
+            .ok()
+            .and_then(|b| b.get().target());
+        let (new_parent, onto) = match parent_oid {
+            Some(oid) if parent != main_branch && !repo.graph_descendant_of(main_oid, oid)? => {
+                (parent.clone(), oid)
+            }
+            _ => (main_branch.to_string(), main_oid),
+        };
+
+        let tip = repo
+            .find_branch(&name, BranchType::Local)?
+            .get()
+            .peel_to_commit()?
+            .id();
+
+        // Rewriting a pushed branch is only safe once we have everything on it: a
+        // teammate's commits on origin would otherwise be lost to the force-push
+        let remote_tip = repo
+            .find_branch(&format!("origin/{name}"), BranchType::Remote)
+            .ok()
+            .and_then(|b| b.get().target());
+        if let Some(remote_tip) = remote_tip
+            && remote_tip != tip
+            && !repo.graph_descendant_of(tip, remote_tip)?
+        {
+            println!(
+                "  - {}: 'origin/{name}' has commits '{}' doesn't. Run 'gg pull' on it first.",
+                "Skipping".yellow(),
+                name.bold()
+            );
+            failed.push(name);
+            continue;
+        }
+        let base = match branch_base(repo, &name) {
+            Some(base) => base,
+            None => repo.merge_base(tip, onto)?,
+        };
+
+        if base == onto {
+            update_branch_parent(repo, &name, &new_parent, onto)?;
+            println!("  - '{}' is up to date with '{new_parent}'", name.bold());
+            continue;
+        }
+
+        let new_tip = show_progress(
+            &format!("Restacking '{}' onto '{}'", name.bold(), new_parent.bold()),
+            || rebase_branch(repo, &name, base, onto),
+        )?;
+
+        let Some(new_tip) = new_tip else {
+            println!(
+                "  - {}: '{name}' conflicts with '{new_parent}'. Rebase it manually.",
+                "Conflict".red()
+            );
+            failed.push(name);
+            continue;
+        };
+
+        repo.find_reference(&format!("refs/heads/{name}"))?
+            .set_target(new_tip, &format!("gg restack: onto {new_parent}"))?;
+        update_branch_parent(repo, &name, &new_parent, onto)?;
+
+        if head_name.as_deref() == Some(name.as_str()) {
+            // The tree is clean (restack requires it), so a forced checkout is safe
+            repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
+        }
+
+        if let Some(remote_tip) = remote_tip
+            && remote_exists
+        {
+            show_progress(&format!("Pushing '{}' with lease", name.bold()), || {
+                push_with_lease(repo, "origin", &name, remote_tip)
+            })?;
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::from_str(&format!(
+            "Could not restack: {}",
+            failed.join(", ")
+        )))
+    }
+}
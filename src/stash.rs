@@ -0,0 +1,300 @@
+use git2::{Error, Oid, Repository, Signature, StashFlags};
+use owo_colors::OwoColorize;
+use serde::Serialize;
+
+use crate::helpers::{committer_signature, is_dirty, show_progress};
+
+/// Prefix marking the message of a stash created with `gg stash <name>`
+const NAME_PREFIX: &str = "gg:";
+
+/// Outcome of re-applying a stash
+pub enum StashRestore {
+    /// The changes were applied cleanly (and dropped, if requested)
+    Clean,
+    /// The changes conflicted with the working tree; the stash was kept
+    Conflicts(Vec<String>),
+}
+
+/// Stashes all local changes, including untracked files.
+/// Returns `None` if there was nothing to stash.
+pub fn stash_changes(repo: &mut Repository, message: &str) -> Result<Option<Oid>, Error> {
+    if !is_dirty(repo)? {
+        return Ok(None);
+    }
+
+    // The stash needs a signature too; don't fail for want of an identity
+    let signature = committer_signature(repo).or_else(|_| Signature::now("gg", "gg@localhost"))?;
+    let oid = repo.stash_save2(
+        &signature,
+        Some(message),
+        Some(StashFlags::INCLUDE_UNTRACKED),
+    )?;
+    Ok(Some(oid))
+}
+
+/// Finds the current position (stash@{n}) of a stash by its commit id
+pub fn find_stash_index(repo: &mut Repository, oid: Oid) -> Result<Option<usize>, Error> {
+    let mut found = None;
+    repo.stash_foreach(|index, _, id| {
+        if *id == oid {
+            found = Some(index);
+            false
+        } else {
+            true
+        }
+    })?;
+    Ok(found)
+}
+
+/// Lists the paths currently conflicted in the index
+fn conflicted_paths(repo: &Repository) -> Result<Vec<String>, Error> {
+    let index = repo.index()?;
+    let mut paths = Vec::new();
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+        if let Some(entry) = entry {
+            paths.push(String::from_utf8_lossy(&entry.path).to_string());
+        }
+    }
+    Ok(paths)
+}
+
+/// Applies a stash, dropping it afterwards if `drop` is set and it applied cleanly.
+/// On conflicts the stash is always kept, so no work can be lost.
+pub fn apply_stash(repo: &mut Repository, oid: Oid, drop: bool) -> Result<StashRestore, Error> {
+    let index = find_stash_index(repo, oid)?
+        .ok_or_else(|| Error::from_str("The stash to restore no longer exists"))?;
+
+    repo.stash_apply(index, None)?;
+
+    let conflicts = conflicted_paths(repo)?;
+    if !conflicts.is_empty() {
+        return Ok(StashRestore::Conflicts(conflicts));
+    }
+
+    if drop {
+        repo.stash_drop(index)?;
+    }
+    Ok(StashRestore::Clean)
+}
+
+/// Re-applies a stash and drops it if it applied cleanly
+pub fn restore_stash(repo: &mut Repository, oid: Oid) -> Result<StashRestore, Error> {
+    apply_stash(repo, oid, true)
+}
+
+/// Prints how to recover a stash that could not be restored automatically
+pub fn print_stash_recovery(repo: &mut Repository, oid: Oid, conflicts: &[String]) {
+    let position = find_stash_index(repo, oid)
+        .ok()
+        .flatten()
+        .map(|i| format!("stash@{{{i}}}"))
+        .unwrap_or_else(|| oid.to_string());
+
+    if !conflicts.is_empty() {
+        println!("\n--- Conflicts while restoring your changes: ---");
+        for path in conflicts {
+            println!("  - {path}");
+        }
+    }
+    println!(
+        "\n{}: Your changes are still saved in {}.",
+        "Note".yellow(),
+        position.bold()
+    );
+    println!("Once the working tree is resolved, drop it with 'gg stash drop {position}'.");
+}
+
+/// Runs `action` with local changes stashed away, then restores them.
+/// The changes are restored even if the action fails.
+pub fn with_autostash<F, R>(repo: &mut Repository, label: &str, action: F) -> Result<R, Error>
+where
+    F: FnOnce(&Repository) -> Result<R, Error>,
+{
+    let message = format!("gg autostash: {label}");
+    let stash = if is_dirty(repo)? {
+        show_progress("Stashing local changes", || stash_changes(repo, &message))?
+    } else {
+        None
+    };
+
+    let result = action(repo);
+
+    if let Some(oid) = stash {
+        match show_progress("Restoring local changes", || restore_stash(repo, oid)) {
+            Ok(StashRestore::Clean) => {}
+            Ok(StashRestore::Conflicts(conflicts)) => {
+                print_stash_recovery(repo, oid, &conflicts);
+            }
+            Err(e) => {
+                eprintln!("Could not restore your changes: {}", e.message());
+                print_stash_recovery(repo, oid, &[]);
+            }
+        }
+    }
+
+    result
+}
+
+/// Runs `action` under `with_autostash` when `enabled`, or directly otherwise
+pub fn autostash_if<F, R>(
+    enabled: bool,
+    repo: &mut Repository,
+    label: &str,
+    action: F,
+) -> Result<R, Error>
+where
+    F: FnOnce(&Repository) -> Result<R, Error>,
+{
+    if enabled {
+        with_autostash(repo, label, action)
+    } else {
+        action(repo)
+    }
+}
+
+/// A stash entry, as shown by `gg stash list`
+#[derive(Debug, Serialize)]
+pub struct StashEntry {
+    pub index: usize,
+    #[serde(skip)]
+    pub oid: Oid,
+    /// The name given with `gg stash <name>`, if any
+    pub name: Option<String>,
+    pub branch: Option<String>,
+    pub message: String,
+    /// Unix timestamp of when the stash was created
+    pub time: i64,
+    pub files: Vec<String>,
+}
+
+/// Splits a stash message ("On main: gg:login-wip") into its branch and description
+fn parse_stash_message(message: &str) -> (Option<String>, String) {
+    for prefix in ["On ", "WIP on "] {
+        if let Some(rest) = message.strip_prefix(prefix)
+            && let Some((branch, description)) = rest.split_once(": ")
+        {
+            return (Some(branch.to_string()), description.to_string());
+        }
+    }
+    (None, message.to_string())
+}
+
+/// Lists the files a stash touches, including stashed untracked files
+fn stash_files(repo: &Repository, oid: Oid) -> Result<Vec<String>, Error> {
+    let commit = repo.find_commit(oid)?;
+    let base_tree = commit.parent(0)?.tree()?;
+
+    let mut files = Vec::new();
+    let mut collect = |diff: git2::Diff| {
+        for delta in diff.deltas() {
+            if let Some(p) = delta.new_file().path().or(delta.old_file().path()) {
+                files.push(p.to_string_lossy().into_owned());
+            }
+        }
+    };
+
+    collect(repo.diff_tree_to_tree(Some(&base_tree), Some(&commit.tree()?), None)?);
+
+    // The third parent, when present, holds the untracked files
+    if let Ok(untracked) = commit.parent(2) {
+        collect(repo.diff_tree_to_tree(None, Some(&untracked.tree()?), None)?);
+    }
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Stashes all local changes under a searchable name
+pub fn stash_named(repo: &mut Repository, name: Option<&str>) -> Result<Option<Oid>, Error> {
+    let message = match name {
+        Some(name) => format!("{NAME_PREFIX}{name}"),
+        None => format!("{NAME_PREFIX}wip"),
+    };
+    stash_changes(repo, &message)
+}
+
+/// Lists all stashes, newest first
+pub fn list_stashes(repo: &mut Repository) -> Result<Vec<StashEntry>, Error> {
+    let mut raw = Vec::new();
+    repo.stash_foreach(|index, message, oid| {
+        raw.push((index, message.to_string(), *oid));
+        true
+    })?;
+
+    let mut entries = Vec::new();
+    for (index, message, oid) in raw {
+        let (branch, description) = parse_stash_message(&message);
+        let name = description.strip_prefix(NAME_PREFIX).map(str::to_string);
+        entries.push(StashEntry {
+            index,
+            oid,
+            name,
+            branch,
+            message: description,
+            time: repo.find_commit(oid)?.time().seconds(),
+            files: stash_files(repo, oid)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Finds a stash by name, by `stash@{n}`/`n`, or by a unique fragment of its message
+pub fn find_stash(repo: &mut Repository, query: &str) -> Result<StashEntry, Error> {
+    let entries = list_stashes(repo)?;
+
+    let position = query
+        .strip_prefix("stash@{")
+        .and_then(|q| q.strip_suffix('}'))
+        .unwrap_or(query)
+        .parse::<usize>()
+        .ok();
+
+    let mut matches: Vec<StashEntry> = Vec::new();
+    for entry in entries {
+        if entry.name.as_deref() == Some(query) || position == Some(entry.index) {
+            return Ok(entry);
+        }
+        if entry.message.contains(query) {
+            matches.push(entry);
+        }
+    }
+
+    match matches.len() {
+        0 => Err(Error::from_str(&format!("No stash matches '{query}'"))),
+        1 => Ok(matches.remove(0)),
+        n => Err(Error::from_str(&format!(
+            "'{query}' matches {n} stashes, please be more specific"
+        ))),
+    }
+}
+
+/// Drops a stash by query, or the latest one when no query is given
+pub fn drop_stash(repo: &mut Repository, query: Option<&str>) -> Result<StashEntry, Error> {
+    let entry = match query {
+        Some(query) => find_stash(repo, query)?,
+        None => list_stashes(repo)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::from_str("There are no stashes"))?,
+    };
+    repo.stash_drop(entry.index)?;
+    Ok(entry)
+}
+
+/// Applies (or pops, if `drop` is set) a stash found by `find_stash`
+pub fn unstash(repo: &mut Repository, query: &str, drop: bool) -> Result<(), Error> {
+    let entry = find_stash(repo, query)?;
+    let label = entry.name.as_deref().unwrap_or(&entry.message).to_string();
+
+    match show_progress(&format!("Applying '{}'", label.bold()), || {
+        apply_stash(repo, entry.oid, drop)
+    })? {
+        StashRestore::Clean if drop => println!("--- Applied and dropped '{label}' ---"),
+        StashRestore::Clean => println!("--- Applied '{label}', stash kept ---"),
+        StashRestore::Conflicts(conflicts) => print_stash_recovery(repo, entry.oid, &conflicts),
+    }
+    Ok(())
+}
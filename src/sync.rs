@@ -0,0 +1,303 @@
+use std::path::PathBuf;
+
+use git2::{BranchType, Error, Oid, Repository};
+use owo_colors::OwoColorize;
+
+use crate::git_commands::{fetch, is_offline_error, offline_error, push, push_with_lease};
+use crate::helpers::{is_dirty, show_progress};
+
+/// Marks a branch whose commits were made offline, for `gg sync` to push later. When those
+/// commits rewrote pushed ones (`--amend`), the remote tip being replaced is recorded too,
+/// so sync can force-push over exactly that tip rather than being rejected.
+pub fn queue_push(repo: &Repository, branch: &str, rewritten: bool) -> Result<(), Error> {
+    let mut config = repo.config()?;
+    config.set_bool(&format!("branch.{branch}.ggPushQueued"), true)?;
+
+    // A second rewrite still replaces the tip recorded by the first one
+    if rewritten
+        && queued_lease(repo, branch).is_none()
+        && let Ok(upstream) = repo.find_branch(branch, BranchType::Local)?.upstream()
+        && let Some(remote_tip) = upstream.get().target()
+    {
+        config.set_str(
+            &format!("branch.{branch}.ggPushLease"),
+            &remote_tip.to_string(),
+        )?;
+    }
+    Ok(())
+}
+
+/// The remote tip a queued rewrite of `branch` is allowed to replace, if any
+pub fn queued_lease(repo: &Repository, branch: &str) -> Option<Oid> {
+    repo.config()
+        .and_then(|mut c| c.snapshot())
+        .and_then(|c| c.get_string(&format!("branch.{branch}.ggPushLease")))
+        .ok()
+        .and_then(|oid| Oid::from_str(&oid).ok())
+}
+
+/// Forgets a queued push, e.g. once the branch has been pushed
+pub fn clear_queued_push(repo: &Repository, branch: &str) -> Result<(), Error> {
+    let mut config = repo.config()?;
+    for key in ["ggPushQueued", "ggPushLease"] {
+        match config.remove(&format!("branch.{branch}.{key}")) {
+            Err(e) if e.code() != git2::ErrorCode::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Lists the branches waiting to be pushed, skipping ones deleted since
+pub fn queued_pushes(repo: &Repository) -> Result<Vec<String>, Error> {
+    let config = repo.config()?.snapshot()?;
+    let mut branches = Vec::new();
+    let mut entries = config.entries(Some(r"branch\..*\.ggpushqueued"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let Some(branch) = entry
+            .name()
+            .and_then(|n| n.strip_prefix("branch."))
+            .and_then(|n| n.strip_suffix(".ggpushqueued"))
+        else {
+            continue;
+        };
+        if entry.value() == Some("true")
+            && repo.find_branch(branch, BranchType::Local).is_ok()
+            && !branches.iter().any(|b| b == branch)
+        {
+            branches.push(branch.to_string());
+        }
+    }
+    Ok(branches)
+}
+
+/// Where a local branch stands against its upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Standing {
+    UpToDate,
+    Behind,
+    Ahead,
+    Diverged,
+}
+
+/// A local branch and the remote branch it tracks
+struct Tracked {
+    branch: String,
+    remote: String,
+    remote_branch: String,
+}
+
+/// Lists the local branches that have an upstream
+fn tracked_branches(repo: &Repository) -> Result<Vec<Tracked>, Error> {
+    let mut tracked = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(refname) = branch.get().name() else {
+            continue;
+        };
+        let (Ok(remote), Ok(merge)) = (
+            repo.branch_upstream_remote(refname),
+            repo.branch_upstream_merge(refname),
+        ) else {
+            continue;
+        };
+        let (Some(remote), Some(merge), Some(name)) =
+            (remote.as_str(), merge.as_str(), branch.name()?)
+        else {
+            continue;
+        };
+        tracked.push(Tracked {
+            branch: name.to_string(),
+            remote: remote.to_string(),
+            remote_branch: merge
+                .strip_prefix("refs/heads/")
+                .unwrap_or(merge)
+                .to_string(),
+        });
+    }
+    Ok(tracked)
+}
+
+fn standing(repo: &Repository, local: Oid, remote: Oid) -> Result<Standing, Error> {
+    Ok(if local == remote {
+        Standing::UpToDate
+    } else if repo.graph_descendant_of(remote, local)? {
+        Standing::Behind
+    } else if repo.graph_descendant_of(local, remote)? {
+        Standing::Ahead
+    } else {
+        Standing::Diverged
+    })
+}
+
+/// The linked worktree that has `branch` checked out, if any
+fn worktree_with_branch(repo: &Repository, branch: &str) -> Result<Option<PathBuf>, Error> {
+    let refname = format!("refs/heads/{branch}");
+    for name in repo.worktrees()?.iter().flatten() {
+        let worktree = repo.find_worktree(name)?;
+        let Ok(linked) = Repository::open_from_worktree(&worktree) else {
+            continue;
+        };
+        if linked.find_reference("HEAD")?.symbolic_target() == Some(refname.as_str()) {
+            return Ok(Some(worktree.path().to_path_buf()));
+        }
+    }
+    Ok(None)
+}
+
+/// Moves a branch forward to `target`. Other branches are just re-pointed; the checked-out
+/// one also updates the working directory, so it is left alone while that has changes.
+/// Branches checked out in another worktree are left for that worktree to update.
+fn fast_forward(repo: &Repository, branch: &str, target: Oid) -> Result<(), Error> {
+    if let Some(path) = worktree_with_branch(repo, branch)? {
+        return Err(Error::from_str(&format!(
+            "it is checked out in the worktree at '{}'; run 'gg pull' there instead",
+            path.display()
+        )));
+    }
+
+    let mut reference = repo.find_reference(&format!("refs/heads/{branch}"))?;
+    let message = format!("gg sync: fast-forward to {target}");
+
+    let is_head = repo
+        .head()
+        .ok()
+        .and_then(|h| h.shorthand().map(str::to_string))
+        == Some(branch.to_string());
+    if !is_head {
+        reference.set_target(target, &message)?;
+        return Ok(());
+    }
+
+    if is_dirty(repo)? {
+        return Err(Error::from_str(
+            "it is checked out with local changes; 'gg save' or 'gg pull' it instead",
+        ));
+    }
+    let tree = repo.find_commit(target)?.into_object();
+    repo.checkout_tree(&tree, Some(git2::build::CheckoutBuilder::default().safe()))?;
+    reference.set_target(target, &message)?;
+    Ok(())
+}
+
+/// Brings every tracked branch level with its upstream in one go: fetches each remote once,
+/// fast-forwards branches that are behind, pushes branches that are ahead (and ones queued by
+/// `--offline`, with a lease when they were amended), and reports diverged branches without
+/// touching them.
+pub fn sync_branches(repo: &Repository) -> Result<(), Error> {
+    let tracked = tracked_branches(repo)?;
+    if tracked.is_empty() && queued_pushes(repo)?.is_empty() {
+        println!("No branches with an upstream to sync.");
+        return Ok(());
+    }
+
+    let mut remotes: Vec<&str> = tracked.iter().map(|t| t.remote.as_str()).collect();
+    remotes.sort_unstable();
+    remotes.dedup();
+    for remote in remotes {
+        show_progress(&format!("Fetching '{remote}'"), || {
+            fetch(repo, remote, false).map_err(|e| {
+                if is_offline_error(&e) {
+                    offline_error(&e, remote)
+                } else {
+                    e
+                }
+            })
+        })?;
+    }
+
+    let mut failed: Vec<String> = Vec::new();
+    for t in &tracked {
+        let label = t.branch.bold();
+        let local = repo.refname_to_id(&format!("refs/heads/{}", t.branch))?;
+        let Ok(remote) =
+            repo.refname_to_id(&format!("refs/remotes/{}/{}", t.remote, t.remote_branch))
+        else {
+            println!(
+                "  {label}: '{}/{}' is gone, skipped",
+                t.remote, t.remote_branch
+            );
+            continue;
+        };
+
+        match standing(repo, local, remote)? {
+            Standing::UpToDate => {
+                // Pushed some other way since it was queued
+                clear_queued_push(repo, &t.branch)?;
+                println!("  {label}: up to date");
+            }
+            Standing::Behind => match fast_forward(repo, &t.branch, remote) {
+                Ok(()) => println!("  {label}: fast-forwarded to {}", &remote.to_string()[..7]),
+                Err(e) => {
+                    println!("  {label}: behind, not updated: {}", e.message());
+                    failed.push(t.branch.clone());
+                }
+            },
+            Standing::Ahead if t.remote_branch != t.branch => println!(
+                "  {label}: ahead of '{}/{}', push it by hand as the names differ",
+                t.remote, t.remote_branch
+            ),
+            Standing::Ahead => {
+                match show_progress(&format!("  {label}: pushing"), || {
+                    push(repo, &t.remote, &t.branch, false)
+                }) {
+                    Ok(()) => {}
+                    Err(e) if is_offline_error(&e) => return Err(e),
+                    Err(e) => {
+                        eprintln!("  {}", e.message());
+                        failed.push(t.branch.clone());
+                    }
+                }
+            }
+            Standing::Diverged => match queued_lease(repo, &t.branch) {
+                // Rewritten offline: replace the remote tip it was rewritten from, and only that
+                Some(lease) if t.remote_branch == t.branch => {
+                    match show_progress(
+                        &format!("  {label}: pushing the rewritten commits"),
+                        || push_with_lease(repo, &t.remote, &t.branch, lease),
+                    ) {
+                        Ok(()) => {}
+                        Err(e) if is_offline_error(&e) => return Err(e),
+                        Err(e) => {
+                            eprintln!("  {}", e.message());
+                            failed.push(t.branch.clone());
+                        }
+                    }
+                }
+                _ => println!(
+                    "  {label}: {} from '{}/{}', run 'gg pull' on it to merge",
+                    "diverged".yellow(),
+                    t.remote,
+                    t.remote_branch
+                ),
+            },
+        }
+    }
+
+    // Branches first committed offline have no upstream to compare against yet
+    for branch in queued_pushes(repo)? {
+        if tracked.iter().any(|t| t.branch == branch) {
+            continue;
+        }
+        match show_progress(&format!("  {}: pushing", branch.bold()), || {
+            push(repo, "origin", &branch, false)
+        }) {
+            Ok(()) => {}
+            Err(e) if is_offline_error(&e) => return Err(e),
+            Err(e) => {
+                eprintln!("  {}", e.message());
+                failed.push(branch);
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::from_str(&format!(
+            "Could not sync {}.",
+            failed.join(", ")
+        )))
+    }
+}